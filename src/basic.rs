@@ -0,0 +1,242 @@
+use crate::{AuthError, Htpasswd, PlaintextPolicy, VerifyError};
+use base64::{
+	alphabet,
+	engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+	Engine,
+};
+use zeroize::Zeroizing;
+
+/// Longest `Authorization` header value that is decoded, in bytes
+pub const MAX_BASIC_AUTH_HEADER_LEN: usize = 4096;
+/// Longest username accepted from an `Authorization` header, in bytes
+pub const MAX_BASIC_AUTH_USERNAME_LEN: usize = 255;
+
+/// Token68 may be sent with or without its `=` padding
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+	&alphabet::STANDARD,
+	GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// User whose credentials were verified by
+/// [`verify_basic_auth_header`](struct.Htpasswd.html#method.verify_basic_auth_header)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuthenticatedUser {
+	pub username: String,
+}
+
+/// `WWW-Authenticate` challenge for a 401 response, asking for UTF-8 credentials as in
+/// RFC 7617 section 2.1
+///
+/// ```
+/// let challenge = htpasswd_verify::basic_auth_challenge("Staff \"only\"");
+/// assert_eq!(challenge, r#"Basic realm="Staff \"only\"", charset="UTF-8""#);
+/// ```
+pub fn basic_auth_challenge(realm: &str) -> String {
+	format!("Basic realm={}, charset=\"UTF-8\"", quoted_string(realm))
+}
+
+/// Wraps the value in quotes, escaping quotes and backslashes inside it
+pub(crate) fn quoted_string(value: &str) -> String {
+	let mut quoted = String::from("\"");
+	for c in value.chars() {
+		if c == '"' || c == '\\' {
+			quoted.push('\\');
+		}
+		quoted.push(c);
+	}
+	quoted.push('"');
+	quoted
+}
+
+impl Htpasswd<'_> {
+	/// Verifies the credentials of an HTTP `Authorization: Basic` header, rejecting plaintext
+	/// entries
+	///
+	/// See [`verify_basic_auth_header_with_policy`](#method.verify_basic_auth_header_with_policy).
+	///
+	/// ```
+	/// use htpasswd_verify::AuthError;
+	///
+	/// let htpasswd = htpasswd_verify::load("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00");
+	/// let user = htpasswd.verify_basic_auth_header("Basic dXNlcjpwYXNzd29yZA==").unwrap();
+	/// assert_eq!(user.username, "user");
+	/// assert_eq!(htpasswd.verify_basic_auth_header("Basic dXNlcjpwYXNzd29ydA=="), Err(AuthError::InvalidCredentials));
+	/// ```
+	pub fn verify_basic_auth_header(&self, header: &str) -> Result<AuthenticatedUser, AuthError> {
+		self.verify_basic_auth_header_with_policy(header, PlaintextPolicy::Reject)
+	}
+
+	/// Verifies the credentials of an HTTP `Authorization: Basic` header as defined by RFC 7617
+	///
+	/// The scheme is matched case-insensitively and the credentials are split on the first
+	/// `:`, so passwords may contain colons. Credentials must be UTF-8, which is what
+	/// [`basic_auth_challenge`](fn.basic_auth_challenge.html) asks clients for with its
+	/// `charset` parameter. Headers longer than
+	/// [`MAX_BASIC_AUTH_HEADER_LEN`](constant.MAX_BASIC_AUTH_HEADER_LEN.html) are rejected
+	/// before decoding.
+	///
+	/// Unknown users and wrong passwords both give
+	/// [`AuthError::InvalidCredentials`](enum.AuthError.html#variant.InvalidCredentials), and
+	/// are verified without revealing through timing whether the user exists.
+	pub fn verify_basic_auth_header_with_policy(
+		&self,
+		header: &str,
+		plaintext: PlaintextPolicy,
+	) -> Result<AuthenticatedUser, AuthError> {
+		let credentials = BasicCredentials::parse(header)?;
+		let username = credentials.username();
+		match self.verify_without_enumeration(username, credentials.password(), plaintext) {
+			Ok(true) => Ok(AuthenticatedUser {
+				username: username.to_string(),
+			}),
+			Ok(false) | Err(VerifyError::UnknownUser) => Err(AuthError::InvalidCredentials),
+			Err(err) => Err(AuthError::Verify(err)),
+		}
+	}
+}
+
+/// Username and password of an `Authorization: Basic` header, wiped when dropped
+pub(crate) struct BasicCredentials {
+	decoded: Zeroizing<String>,
+	/// Byte offset of the first `:`
+	separator: usize,
+}
+
+impl BasicCredentials {
+	/// Decodes and validates the header, see
+	/// [`verify_basic_auth_header_with_policy`](struct.Htpasswd.html#method.verify_basic_auth_header_with_policy)
+	pub(crate) fn parse(header: &str) -> Result<Self, AuthError> {
+		if header.len() > MAX_BASIC_AUTH_HEADER_LEN {
+			return Err(AuthError::TooLong);
+		}
+		let header = header.trim_matches(|c| c == ' ' || c == '\t');
+		let (scheme, token) = header.split_once(' ').ok_or(AuthError::UnsupportedScheme)?;
+		if !scheme.eq_ignore_ascii_case("Basic") {
+			return Err(AuthError::UnsupportedScheme);
+		}
+		let token = token.trim_start_matches(' ');
+
+		let decoded = Zeroizing::new(BASE64.decode(token).map_err(|_| AuthError::Malformed)?);
+		let decoded = Zeroizing::new(
+			std::str::from_utf8(&decoded)
+				.map_err(|_| AuthError::InvalidEncoding)?
+				.to_string(),
+		);
+		let separator = decoded.find(':').ok_or(AuthError::Malformed)?;
+		if separator > MAX_BASIC_AUTH_USERNAME_LEN {
+			return Err(AuthError::TooLong);
+		}
+		if decoded.chars().any(char::is_control) {
+			return Err(AuthError::Malformed);
+		}
+		Ok(BasicCredentials { decoded, separator })
+	}
+
+	pub(crate) fn username(&self) -> &str {
+		&self.decoded[..self.separator]
+	}
+
+	pub(crate) fn password(&self) -> &str {
+		&self.decoded[(self.separator + 1)..]
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::load;
+	use base64::engine::general_purpose::STANDARD;
+
+	static DATA: &str = "user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00
+colon:{SHA}JfOwpCDy5ASLqp0M24rdutzyYk8=
+plain:password
+bad:$2y$05$short";
+
+	fn header(credentials: &[u8]) -> String {
+		format!("Basic {}", STANDARD.encode(credentials))
+	}
+
+	#[test]
+	fn accepts_valid_credentials() {
+		let htpasswd = load(DATA);
+		let user = AuthenticatedUser {
+			username: "user".to_string(),
+		};
+		assert_eq!(
+			htpasswd.verify_basic_auth_header(&header(b"user:password")),
+			Ok(user.clone())
+		);
+		// Scheme is case-insensitive, padding is optional and surrounding whitespace is ignored
+		assert_eq!(
+			htpasswd.verify_basic_auth_header("  bAsIc   dXNlcjpwYXNzd29yZA \t"),
+			Ok(user)
+		);
+		// Only the first colon separates the username
+		assert_eq!(
+			htpasswd
+				.verify_basic_auth_header(&header(b"colon:pass:word"))
+				.map(|user| user.username),
+			Ok("colon".to_string())
+		);
+		assert_eq!(
+			htpasswd.verify_basic_auth_header_with_policy(
+				&header(b"plain:password"),
+				PlaintextPolicy::AllowPlaintext
+			),
+			Ok(AuthenticatedUser {
+				username: "plain".to_string()
+			})
+		);
+	}
+
+	#[test]
+	fn rejects_invalid_headers() {
+		let htpasswd = load(DATA);
+		let verify = |header: &str| htpasswd.verify_basic_auth_header(header);
+		assert_eq!(
+			verify(&header(b"user:passwort")),
+			Err(AuthError::InvalidCredentials)
+		);
+		assert_eq!(
+			verify(&header(b"nobody:password")),
+			Err(AuthError::InvalidCredentials)
+		);
+		assert_eq!(
+			verify(&header(b"plain:password")),
+			Err(AuthError::Verify(VerifyError::PlaintextRejected))
+		);
+		assert_eq!(
+			verify(&header(b"bad:password")),
+			Err(AuthError::Verify(VerifyError::MalformedHash))
+		);
+		assert_eq!(
+			verify("Bearer dXNlcjpwYXNzd29yZA=="),
+			Err(AuthError::UnsupportedScheme)
+		);
+		assert_eq!(verify("Basic"), Err(AuthError::UnsupportedScheme));
+		assert_eq!(
+			verify("Basicdxnlcjpwyxnzd29yza=="),
+			Err(AuthError::UnsupportedScheme)
+		);
+		assert_eq!(
+			verify("Basic dXNlcjpw!XNzd29yZA=="),
+			Err(AuthError::Malformed)
+		);
+		assert_eq!(verify(&header(b"userpassword")), Err(AuthError::Malformed));
+		assert_eq!(
+			verify(&header(b"user:pass\nword")),
+			Err(AuthError::Malformed)
+		);
+		assert_eq!(
+			verify(&header(b"us\xe9r:password")),
+			Err(AuthError::InvalidEncoding)
+		);
+		let long_username = format!("{}:password", "u".repeat(MAX_BASIC_AUTH_USERNAME_LEN + 1));
+		assert_eq!(
+			verify(&header(long_username.as_bytes())),
+			Err(AuthError::TooLong)
+		);
+		let long_header = format!("Basic {}", "A".repeat(MAX_BASIC_AUTH_HEADER_LEN));
+		assert_eq!(verify(&long_header), Err(AuthError::TooLong));
+	}
+}
@@ -0,0 +1,342 @@
+use crate::watch::Source;
+use crate::{Htpasswd, HtpasswdWatcher, PlaintextPolicy, VerifyError};
+use crypto::{hmac::Hmac, mac::Mac, sha2::Sha256};
+use password_hash::rand_core::{OsRng, RngCore};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// Options for [`CachingVerifier`](struct.CachingVerifier.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheOptions {
+	/// How long a successful verification is remembered, 1 minute by default
+	pub ttl: Duration,
+	/// How many verifications are remembered at most, 1024 by default
+	pub max_entries: usize,
+}
+
+impl Default for CacheOptions {
+	fn default() -> Self {
+		CacheOptions {
+			ttl: Duration::from_secs(60),
+			max_entries: 1024,
+		}
+	}
+}
+
+/// Verifies passwords against an htpasswd file, remembering successful verifications
+///
+/// Clients using Basic auth send the same credentials with every request, which makes slow
+/// hashes like bcrypt expensive. Successful verifications are remembered under an HMAC-SHA256
+/// of the username, password and stored hash, keyed with a random key that never leaves the
+/// verifier, so the cache holds no passwords and nothing that can be checked offline. Wrong
+/// passwords are never cached.
+///
+/// An entry stops matching when the user's stored hash changes, and the whole cache is cleared
+/// when a watched file is reloaded. Unknown users are verified against a dummy hash like
+/// [`Htpasswd::verify_without_enumeration`](struct.Htpasswd.html#method.verify_without_enumeration)
+/// does, so they can't be told apart from wrong passwords.
+///
+/// ```
+/// use htpasswd_verify::{CacheOptions, CachingVerifier};
+/// use std::sync::Arc;
+///
+/// let htpasswd = htpasswd_verify::load("user:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa");
+/// let verifier = CachingVerifier::new(Arc::new(htpasswd.into_owned()), CacheOptions::default());
+/// assert!(verifier.check("user", "password"));
+/// assert!(verifier.check("user", "password"));
+/// assert!(!verifier.check("user", "passwort"));
+/// ```
+#[derive(Debug)]
+pub struct CachingVerifier {
+	source: Source,
+	options: CacheOptions,
+	key: Zeroizing<[u8; 32]>,
+	state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+	/// Version of the file the entries were verified against
+	htpasswd: Option<Arc<Htpasswd<'static>>>,
+	/// When each verification was cached
+	entries: HashMap<[u8; 32], Instant>,
+}
+
+impl CachingVerifier {
+	pub fn new(htpasswd: Arc<Htpasswd<'static>>, options: CacheOptions) -> Self {
+		CachingVerifier::with_source(Source::Fixed(htpasswd), options)
+	}
+
+	/// Verifies against the latest version of a watched file
+	pub fn watched(watcher: Arc<HtpasswdWatcher>, options: CacheOptions) -> Self {
+		CachingVerifier::with_source(Source::Watched(watcher), options)
+	}
+
+	fn with_source(source: Source, options: CacheOptions) -> Self {
+		let mut key = Zeroizing::new([0u8; 32]);
+		OsRng.fill_bytes(&mut *key);
+		CachingVerifier {
+			source,
+			options,
+			key,
+			state: Mutex::default(),
+		}
+	}
+
+	/// Verifies the user's password, rejecting plaintext entries
+	pub fn check(&self, username: &str, password: impl AsRef<str>) -> bool {
+		self.verify(username, password).unwrap_or(false)
+	}
+
+	/// Verifies the user's password, rejecting plaintext entries
+	///
+	/// See [`Htpasswd::verify`](struct.Htpasswd.html#method.verify).
+	pub fn verify(&self, username: &str, password: impl AsRef<str>) -> Result<bool, VerifyError> {
+		self.verify_with_policy(username, password, PlaintextPolicy::Reject)
+	}
+
+	pub fn verify_with_policy(
+		&self,
+		username: &str,
+		password: impl AsRef<str>,
+		plaintext: PlaintextPolicy,
+	) -> Result<bool, VerifyError> {
+		let password = password.as_ref();
+		let htpasswd = self.source.load();
+		let hash = match htpasswd.0.get(username) {
+			Some(hash) => hash,
+			// Against a dummy hash, so unknown users take as long as wrong passwords
+			None => return htpasswd.verify_without_enumeration(username, password, plaintext),
+		};
+		let key = self.cache_key(username, password, &hash.to_string(), plaintext);
+
+		let now = Instant::now();
+		{
+			let mut state = self.lock();
+			state.sync(&htpasswd);
+			if let Some(&cached) = state.entries.get(&key) {
+				if now.duration_since(cached) < self.options.ttl {
+					return Ok(true);
+				}
+				state.entries.remove(&key);
+			}
+		}
+
+		let valid = hash.verify_with_policy(password, plaintext)?;
+		if valid && self.options.max_entries > 0 {
+			let mut state = self.lock();
+			// Don't bring entries of an older version into a cache that was cleared meanwhile
+			if state.is_for(&htpasswd) {
+				self.make_room(&mut state.entries);
+				state.entries.insert(key, Instant::now());
+			}
+		}
+		Ok(valid)
+	}
+
+	/// Forgets all cached verifications
+	pub fn clear(&self) {
+		self.lock().entries.clear();
+	}
+
+	fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+		self.state.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	fn cache_key(
+		&self,
+		username: &str,
+		password: &str,
+		hash: &str,
+		plaintext: PlaintextPolicy,
+	) -> [u8; 32] {
+		let mut mac = Hmac::new(Sha256::new(), &*self.key);
+		// Length prefixes keep the fields from running into each other
+		for field in [username, hash, password] {
+			mac.input(&(field.len() as u64).to_le_bytes());
+			mac.input(field.as_bytes());
+		}
+		mac.input(&[plaintext as u8]);
+		let mut key = [0u8; 32];
+		mac.raw_result(&mut key);
+		mac.reset();
+		key
+	}
+
+	/// Removes expired entries if the cache is full, and the oldest one if that's not enough
+	fn make_room(&self, entries: &mut HashMap<[u8; 32], Instant>) {
+		if entries.len() < self.options.max_entries {
+			return;
+		}
+		let ttl = self.options.ttl;
+		entries.retain(|_, cached| cached.elapsed() < ttl);
+		while entries.len() >= self.options.max_entries {
+			let oldest = *entries
+				.iter()
+				.min_by_key(|(_, &cached)| cached)
+				.map(|(key, _)| key)
+				.expect("a full cache has entries");
+			entries.remove(&oldest);
+		}
+	}
+}
+
+impl CacheState {
+	/// Clears the entries if they were cached against another version of the file
+	fn sync(&mut self, htpasswd: &Arc<Htpasswd<'static>>) {
+		if !self.is_for(htpasswd) {
+			self.entries.clear();
+			self.htpasswd = Some(Arc::clone(htpasswd));
+		}
+	}
+
+	fn is_for(&self, htpasswd: &Arc<Htpasswd<'static>>) -> bool {
+		matches!(&self.htpasswd, Some(current) if Arc::ptr_eq(current, htpasswd))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::{test_dir, CONSTANT_TIME_EQ_CALLS};
+	use crate::{load, WatchOptions};
+	use std::cell::Cell;
+	use std::fs;
+
+	static DATA: &str = "user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00
+crypt_test:bGVh02xkuGli2
+plain:password";
+
+	/// Number of hashes computed by `f`
+	fn verifications(f: impl FnOnce()) -> usize {
+		CONSTANT_TIME_EQ_CALLS.with(|calls| calls.set(0));
+		f();
+		CONSTANT_TIME_EQ_CALLS.with(Cell::get)
+	}
+
+	fn verifier(options: CacheOptions) -> CachingVerifier {
+		CachingVerifier::new(Arc::new(load(DATA).into_owned()), options)
+	}
+
+	#[test]
+	fn caches_successful_verifications() {
+		let verifier = verifier(CacheOptions::default());
+		assert_eq!(
+			verifications(|| assert!(verifier.check("user", "password"))),
+			1
+		);
+		assert_eq!(
+			verifications(|| assert!(verifier.check("user", "password"))),
+			0
+		);
+		for _ in 0..2 {
+			assert_eq!(
+				verifications(|| assert!(!verifier.check("user", "passwort"))),
+				1
+			);
+		}
+		assert_eq!(
+			verifier.verify("nobody", "password"),
+			Err(VerifyError::UnknownUser)
+		);
+		// Unknown users take as long as wrong passwords
+		let apr1 = CachingVerifier::new(
+			Arc::new(load("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00").into_owned()),
+			CacheOptions::default(),
+		);
+		for _ in 0..2 {
+			assert_eq!(
+				verifications(|| assert!(!apr1.check("nobody", "password"))),
+				1
+			);
+		}
+
+		// A success with plaintext allowed doesn't carry over to a check that rejects it
+		let policy = PlaintextPolicy::AllowPlaintext;
+		assert_eq!(
+			verifier.verify_with_policy("plain", "password", policy),
+			Ok(true)
+		);
+		assert_eq!(
+			verifier.verify("plain", "password"),
+			Err(VerifyError::PlaintextRejected)
+		);
+
+		verifier.clear();
+		assert_eq!(
+			verifications(|| assert!(verifier.check("user", "password"))),
+			1
+		);
+	}
+
+	#[test]
+	fn respects_ttl_and_size() {
+		let verifier = self::verifier(CacheOptions {
+			ttl: Duration::ZERO,
+			..CacheOptions::default()
+		});
+		for _ in 0..2 {
+			assert_eq!(
+				verifications(|| assert!(verifier.check("user", "password"))),
+				1
+			);
+		}
+
+		let verifier = self::verifier(CacheOptions {
+			max_entries: 1,
+			..CacheOptions::default()
+		});
+		assert!(verifier.check("user", "password"));
+		assert!(verifier.check("crypt_test", "password"));
+		assert_eq!(verifier.lock().entries.len(), 1);
+		assert_eq!(
+			verifications(|| assert!(verifier.check("crypt_test", "password"))),
+			0
+		);
+		assert_eq!(
+			verifications(|| assert!(verifier.check("user", "password"))),
+			1
+		);
+	}
+
+	#[test]
+	fn reload_invalidates() {
+		let dir = test_dir("cache");
+		let path = dir.join("htpasswd");
+		fs::write(&path, DATA).unwrap();
+		let options = WatchOptions {
+			poll_interval: Duration::from_secs(3600),
+			force_polling: true,
+		};
+		let watcher = Arc::new(HtpasswdWatcher::watch_with(&path, options, |_| {}).unwrap());
+		let verifier = CachingVerifier::watched(Arc::clone(&watcher), CacheOptions::default());
+		assert!(verifier.check("user", "password"));
+		assert!(verifier.check("crypt_test", "password"));
+
+		// Same hash for crypt_test, new one for user
+		fs::write(
+			&path,
+			"user:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\ncrypt_test:bGVh02xkuGli2\n",
+		)
+		.unwrap();
+		watcher.reload();
+		assert_eq!(
+			verifications(|| assert!(verifier.check("crypt_test", "password"))),
+			1
+		);
+		assert_eq!(
+			verifications(|| assert!(verifier.check("crypt_test", "password"))),
+			0
+		);
+		assert_eq!(
+			verifications(|| assert!(verifier.check("user", "password"))),
+			1
+		);
+		assert!(!verifier.check("user", "passwort"));
+		drop(verifier);
+		drop(watcher);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,64 @@
+use crate::ParseErrorKind;
+use std::fmt;
+
+/// Problem found by [`load_with_diagnostics`](fn.load_with_diagnostics.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+	/// 1-based line number of the entry
+	pub line: usize,
+	/// 1-based byte column at which the problem was detected
+	pub column: usize,
+	/// Username of the entry, if the line got far enough to have one
+	pub username: Option<String>,
+	pub kind: DiagnosticKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+	/// Entry has no `:` separator or no username and was left out
+	Skipped(ParseErrorKind),
+	/// Hash failed to parse, the entry is kept but never verifies
+	MalformedHash(ParseErrorKind),
+	/// Username was already defined, this entry is ignored and the one on `previous_line` is in
+	/// effect
+	DuplicateUsername { previous_line: usize },
+	/// Whitespace after the hash, which was trimmed
+	TrailingWhitespace,
+	/// Entry stores a plaintext password
+	Plaintext,
+	/// Hash format isn't supported, the entry never verifies
+	UnknownFormat,
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"line {}, column {}: {}",
+			self.line, self.column, self.kind
+		)?;
+		if let Some(username) = &self.username {
+			write!(f, " (user {:?})", username)?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Display for DiagnosticKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DiagnosticKind::Skipped(kind) => write!(f, "skipped, {}", kind),
+			DiagnosticKind::MalformedHash(kind) => write!(f, "malformed hash, {}", kind),
+			DiagnosticKind::DuplicateUsername { previous_line } => {
+				write!(
+					f,
+					"duplicate username, ignored, line {} is in effect",
+					previous_line
+				)
+			}
+			DiagnosticKind::TrailingWhitespace => f.write_str("trailing whitespace"),
+			DiagnosticKind::Plaintext => f.write_str("plaintext password"),
+			DiagnosticKind::UnknownFormat => f.write_str("unknown hash format"),
+		}
+	}
+}
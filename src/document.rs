@@ -0,0 +1,379 @@
+use crate::edit::{parse_new_hash, validate_username};
+use crate::{
+	owned, parse_hash_entry, Hash, Htpasswd, Params, PlaintextPolicy, Scheme, UpdateError,
+	VerifyError,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// htpasswd file that keeps every line, for editing without reformatting the file
+///
+/// Comments, blank lines, lines without a `:`, line endings and the order of the entries are
+/// written back exactly as they were read, only the entries that were changed are rewritten.
+/// Like [`load`](fn.load.html), malformed hashes are kept but never verify. When a username
+/// has several entries, the first one is in effect, as in Apache.
+///
+/// ```
+/// use htpasswd_verify::HtpasswdDocument;
+///
+/// let data = "# admins\nadmin:bGVh02xkuGli2\n\n# team a\nuser:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n";
+/// let mut document = HtpasswdDocument::parse(data);
+/// document.insert_hash("admin", "$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00").unwrap();
+/// assert!(document.check("admin", "password"));
+/// assert_eq!(document.to_string(), data.replace("admin:bGVh02xkuGli2", "admin:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct HtpasswdDocument<'a> {
+	lines: Vec<Line<'a>>,
+}
+
+/// Original text of a line without the `\n`, and the entry if the line has one
+#[derive(Debug, Clone)]
+struct Line<'a> {
+	raw: Cow<'a, str>,
+	entry: Option<(Cow<'a, str>, Hash<'a>)>,
+}
+
+impl<'a> Line<'a> {
+	fn parse(raw: &'a str) -> Self {
+		let content = raw.strip_suffix('\r').unwrap_or(raw);
+		let entry = Some(content)
+			.filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+			.and_then(parse_hash_entry)
+			.map(|(username, hash)| (username.into(), hash));
+		Line {
+			raw: raw.into(),
+			entry,
+		}
+	}
+
+	fn entry(username: String, hash: Hash<'a>, crlf: bool) -> Self {
+		let line_ending = if crlf { "\r" } else { "" };
+		Line {
+			raw: format!("{}:{}{}", username, hash, line_ending).into(),
+			entry: Some((username.into(), hash)),
+		}
+	}
+
+	fn username(&self) -> Option<&str> {
+		self.entry.as_ref().map(|(username, _)| username.as_ref())
+	}
+
+	fn is_crlf(&self) -> bool {
+		self.raw.ends_with('\r')
+	}
+
+	fn into_owned(self) -> Line<'static> {
+		Line {
+			raw: owned(self.raw),
+			entry: self
+				.entry
+				.map(|(username, hash)| (owned(username), hash.into_owned())),
+		}
+	}
+}
+
+impl<'a> HtpasswdDocument<'a> {
+	/// Parses an htpasswd file, never fails since every line is kept
+	pub fn parse(bytes: &'a str) -> Self {
+		HtpasswdDocument {
+			lines: bytes.split('\n').map(Line::parse).collect(),
+		}
+	}
+
+	/// Hash of the user, from the first entry with their username
+	pub fn get(&self, username: &str) -> Option<&Hash<'a>> {
+		self.position(username)
+			.and_then(|idx| self.lines[idx].entry.as_ref())
+			.map(|(_, hash)| hash)
+	}
+
+	/// Usernames in file order, including duplicates
+	pub fn usernames(&self) -> impl Iterator<Item = &str> {
+		self.lines.iter().filter_map(Line::username)
+	}
+
+	/// Verifies the user's password, rejecting plaintext entries
+	pub fn check(&self, username: &str, password: impl AsRef<str>) -> bool {
+		self.verify(username, password).unwrap_or(false)
+	}
+
+	/// Verifies the user's password, rejecting plaintext entries
+	///
+	/// See [`Htpasswd::verify`](struct.Htpasswd.html#method.verify).
+	pub fn verify(&self, username: &str, password: impl AsRef<str>) -> Result<bool, VerifyError> {
+		self.verify_with_policy(username, password, PlaintextPolicy::Reject)
+	}
+
+	pub fn verify_with_policy(
+		&self,
+		username: &str,
+		password: impl AsRef<str>,
+		plaintext: PlaintextPolicy,
+	) -> Result<bool, VerifyError> {
+		self.get(username)
+			.ok_or(VerifyError::UnknownUser)?
+			.verify_with_policy(password, plaintext)
+	}
+
+	/// Hashes the password with the default [`Params`](struct.Params.html) and stores it.
+	///
+	/// The user's entry is rewritten in place, new users are appended at the end of the file.
+	pub fn set_password(
+		&mut self,
+		username: &str,
+		password: &str,
+		scheme: Scheme,
+	) -> Result<(), UpdateError> {
+		self.set_password_with_params(username, password, scheme, &Params::default())
+	}
+
+	pub fn set_password_with_params(
+		&mut self,
+		username: &str,
+		password: &str,
+		scheme: Scheme,
+		params: &Params,
+	) -> Result<(), UpdateError> {
+		validate_username(username)?;
+		let hash = Hash::generate(password, scheme, params)?;
+		self.insert_hash(username, &hash)
+	}
+
+	/// Stores an already hashed password, see
+	/// [`Htpasswd::insert_hash`](struct.Htpasswd.html#method.insert_hash).
+	///
+	/// Only the user's first entry, the one in effect, is rewritten in place. Later duplicates
+	/// are left as they are. New users are appended at the end of the file.
+	pub fn insert_hash(&mut self, username: &str, hash: &str) -> Result<(), UpdateError> {
+		validate_username(username)?;
+		let hash = parse_new_hash(hash)?;
+		match self.position(username) {
+			Some(idx) => {
+				let crlf = self.lines[idx].is_crlf();
+				self.lines[idx] = Line::entry(username.to_string(), hash, crlf);
+			}
+			None => {
+				let crlf = self.lines.iter().any(Line::is_crlf);
+				let line = Line::entry(username.to_string(), hash, crlf);
+				// Keep the final newline, which splits off an empty last line
+				match self.lines.last() {
+					Some(last) if last.raw.is_empty() => {
+						self.lines.insert(self.lines.len() - 1, line)
+					}
+					_ => self.lines.push(line),
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Removes every entry of the user, returning the hash that was in effect
+	pub fn remove_user(&mut self, username: &str) -> Option<Hash<'a>> {
+		let idx = self.position(username)?;
+		let (_, hash) = self.lines.remove(idx).entry?;
+		self.lines.retain(|line| line.username() != Some(username));
+		Some(hash)
+	}
+
+	/// Renames every entry of the user, keeping their hashes and position in the file
+	pub fn rename_user(&mut self, username: &str, new_username: &str) -> Result<(), UpdateError> {
+		validate_username(new_username)?;
+		if self.position(username).is_none() {
+			return Err(UpdateError::UnknownUser);
+		}
+		if username == new_username {
+			return Ok(());
+		}
+		if self.position(new_username).is_some() {
+			return Err(UpdateError::DuplicateUsername);
+		}
+		for line in self.lines.iter_mut() {
+			if line.username() == Some(username) {
+				let crlf = line.is_crlf();
+				if let Some((_, hash)) = line.entry.take() {
+					*line = Line::entry(new_username.to_string(), hash, crlf);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Writes the file back in the same format as `to_string()`
+	pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+		write!(writer, "{}", self)
+	}
+
+	/// Entries of the file, without the comments and unknown lines, keeping the first entry of
+	/// each user
+	pub fn to_htpasswd(&self) -> Htpasswd<'a> {
+		let mut entries = HashMap::new();
+		for (username, hash) in self.lines.iter().filter_map(|line| line.entry.as_ref()) {
+			entries
+				.entry(username.clone())
+				.or_insert_with(|| hash.clone());
+		}
+		Htpasswd(entries)
+	}
+
+	/// Copies the lines so the document outlives the parsed string
+	pub fn into_owned(self) -> HtpasswdDocument<'static> {
+		HtpasswdDocument {
+			lines: self.lines.into_iter().map(Line::into_owned).collect(),
+		}
+	}
+
+	/// Index of the first entry of the user, the one that is used for verification
+	fn position(&self, username: &str) -> Option<usize> {
+		self.lines
+			.iter()
+			.position(|line| line.username() == Some(username))
+	}
+}
+
+/// Formats the document as the original file, with the changed entries rewritten
+impl fmt::Display for HtpasswdDocument<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (idx, line) in self.lines.iter().enumerate() {
+			if idx > 0 {
+				f.write_str("\n")?;
+			}
+			f.write_str(&line.raw)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	static DATA: &str = "# Managed by ops, one section per team
+#
+# team a
+user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00
+crypt_test:bGVh02xkuGli2
+
+not an entry
+# team b
+broken:$apr1$lZL6
+dup:bGVh02xkuGli2
+dup:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=
+#admin:bGVh02xkuGli2
+";
+
+	#[test]
+	fn document_round_trip() {
+		for &data in [DATA, "", "\n", "user:x", "a:b\r\nc:d\r\n", "\r\n\n#\n"].iter() {
+			assert_eq!(HtpasswdDocument::parse(data).to_string(), data);
+		}
+		let document = HtpasswdDocument::parse(DATA);
+		assert_eq!(
+			document.usernames().collect::<Vec<_>>(),
+			["user", "crypt_test", "broken", "dup", "dup"]
+		);
+		assert!(document.check("user", "password"));
+		assert!(matches!(document.get("dup"), Some(Hash::Crypt(_))));
+		assert!(document.get("#admin").is_none());
+		assert_eq!(
+			document.verify("broken", "password"),
+			Err(VerifyError::MalformedHash)
+		);
+		assert_eq!(document.to_htpasswd().0.len(), 4);
+	}
+
+	#[test]
+	fn document_edit_changes_one_line() {
+		let mut document = HtpasswdDocument::parse(DATA);
+		document
+			.insert_hash("crypt_test", "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=")
+			.unwrap();
+		// Only the first entry, the one in effect, changes
+		document
+			.insert_hash("dup", "$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00")
+			.unwrap();
+		document.insert_hash("new", "bGVh02xkuGli2").unwrap();
+		assert_eq!(
+			document.to_string(),
+			DATA.replace(
+				"crypt_test:bGVh02xkuGli2",
+				"crypt_test:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g="
+			)
+			.replace(
+				"dup:bGVh02xkuGli2\n",
+				"dup:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00\n"
+			)
+			.replace(
+				"#admin:bGVh02xkuGli2\n",
+				"#admin:bGVh02xkuGli2\nnew:bGVh02xkuGli2\n"
+			)
+		);
+		assert!(matches!(document.get("dup"), Some(Hash::MD5(_))));
+		assert_eq!(
+			document.usernames().filter(|&name| name == "dup").count(),
+			2
+		);
+		assert_eq!(
+			document.insert_hash("#admin", "bGVh02xkuGli2"),
+			Err(UpdateError::InvalidUsername)
+		);
+		assert!(document.check("crypt_test", "password"));
+		assert_eq!(
+			document.insert_hash("new", "$apr1$lZL6"),
+			Err(UpdateError::InvalidHash(
+				crate::ParseErrorKind::TruncatedApr1Salt
+			))
+		);
+	}
+
+	#[test]
+	fn document_remove_and_rename() {
+		let mut document = HtpasswdDocument::parse(DATA);
+		assert!(document.remove_user("dup").is_some());
+		assert!(document.get("dup").is_none());
+		document.rename_user("user", "renamed").unwrap();
+		assert_eq!(
+			document.rename_user("renamed", "crypt_test"),
+			Err(UpdateError::DuplicateUsername)
+		);
+		assert_eq!(
+			document.rename_user("user", "other"),
+			Err(UpdateError::UnknownUser)
+		);
+		assert!(document.check("renamed", "password"));
+		let expected = DATA
+			.replace(
+				"dup:bGVh02xkuGli2\ndup:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n",
+				"",
+			)
+			.replace("user:$apr1", "renamed:$apr1");
+		assert_eq!(document.to_string(), expected);
+	}
+
+	#[test]
+	fn document_keeps_line_endings() {
+		let mut document = HtpasswdDocument::parse("a:bGVh02xkuGli2\r\n# b\r\n");
+		document
+			.insert_hash("a", "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=")
+			.unwrap();
+		document.insert_hash("c", "bGVh02xkuGli2").unwrap();
+		assert_eq!(
+			document.to_string(),
+			"a:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\r\n# b\r\nc:bGVh02xkuGli2\r\n"
+		);
+
+		let mut document = HtpasswdDocument::parse("a:bGVh02xkuGli2");
+		document.insert_hash("c", "bGVh02xkuGli2").unwrap();
+		assert_eq!(document.to_string(), "a:bGVh02xkuGli2\nc:bGVh02xkuGli2");
+	}
+
+	#[test]
+	fn document_into_owned() {
+		let data = DATA.to_string();
+		let document = HtpasswdDocument::parse(&data).into_owned();
+		drop(data);
+		assert_eq!(document.to_string(), DATA);
+	}
+}
@@ -0,0 +1,266 @@
+use crate::{parse_hash, Hash, Htpasswd, Params, ParseErrorKind, Scheme, UpdateError};
+use std::fmt;
+use std::io::{self, Write};
+
+impl<'a> Htpasswd<'a> {
+	/// Hashes the password with the default [`Params`](struct.Params.html) and stores it,
+	/// adding the user if they don't exist yet.
+	///
+	/// ```
+	/// use htpasswd_verify::{Htpasswd, Scheme};
+	///
+	/// let mut htpasswd = Htpasswd::parse("").unwrap();
+	/// htpasswd.set_password("user", "password", Scheme::Apr1).unwrap();
+	/// assert!(htpasswd.check("user", "password"));
+	/// ```
+	pub fn set_password(
+		&mut self,
+		username: &str,
+		password: &str,
+		scheme: Scheme,
+	) -> Result<(), UpdateError> {
+		self.set_password_with_params(username, password, scheme, &Params::default())
+	}
+
+	pub fn set_password_with_params(
+		&mut self,
+		username: &str,
+		password: &str,
+		scheme: Scheme,
+		params: &Params,
+	) -> Result<(), UpdateError> {
+		validate_username(username)?;
+		let hash = Hash::generate(password, scheme, params)?;
+		self.insert_hash(username, &hash)
+	}
+
+	/// Stores an already hashed password, adding the user if they don't exist yet.
+	///
+	/// The hash is rejected if it's malformed, but like in a file plaintext and unknown formats
+	/// are accepted.
+	pub fn insert_hash(&mut self, username: &str, hash: &str) -> Result<(), UpdateError> {
+		validate_username(username)?;
+		let hash = parse_new_hash(hash)?;
+		self.0.insert(username.to_string().into(), hash);
+		Ok(())
+	}
+
+	/// Removes the user, returning their hash if they existed
+	pub fn remove_user(&mut self, username: &str) -> Option<Hash<'a>> {
+		self.0.remove(username)
+	}
+
+	/// Moves the user's hash to a new username
+	pub fn rename_user(&mut self, username: &str, new_username: &str) -> Result<(), UpdateError> {
+		validate_username(new_username)?;
+		if username == new_username {
+			return if self.0.contains_key(username) {
+				Ok(())
+			} else {
+				Err(UpdateError::UnknownUser)
+			};
+		}
+		if self.0.contains_key(new_username) {
+			return Err(UpdateError::DuplicateUsername);
+		}
+		let hash = self.0.remove(username).ok_or(UpdateError::UnknownUser)?;
+		self.0.insert(new_username.to_string().into(), hash);
+		Ok(())
+	}
+
+	/// Writes the entries in the same format as `to_string()`
+	pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+		write!(writer, "{}", self)
+	}
+}
+
+/// Formats the entries as an htpasswd file, one `user:hash` line per user sorted by username
+///
+/// ```
+/// let htpasswd = htpasswd_verify::load("b:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\na:bGVh02xkuGli2");
+/// assert_eq!(htpasswd.to_string(), "a:bGVh02xkuGli2\nb:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n");
+/// ```
+impl fmt::Display for Htpasswd<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut entries = self.0.iter().collect::<Vec<_>>();
+		entries.sort_by(|a, b| a.0.cmp(b.0));
+		for (username, hash) in entries {
+			writeln!(f, "{}:{}", username, hash)?;
+		}
+		Ok(())
+	}
+}
+
+pub(crate) fn validate_username(username: &str) -> Result<(), UpdateError> {
+	// A leading `#` would turn the line into a comment
+	if !is_valid_field(username) || username.starts_with('#') {
+		return Err(UpdateError::InvalidUsername);
+	}
+	Ok(())
+}
+
+/// Whether the value can be written as one `:` separated field of a line
+pub(crate) fn is_valid_field(value: &str) -> bool {
+	!value.is_empty() && !value.contains([':', '\n', '\r'])
+}
+
+/// Parses a hash that is about to be written to a file
+///
+/// Trailing whitespace is rejected even for plaintext passwords, since readers trim it.
+pub(crate) fn parse_new_hash(hash: &str) -> Result<Hash<'static>, UpdateError> {
+	if hash.contains(['\n', '\r']) {
+		return Err(UpdateError::LineBreak);
+	}
+	if hash.trim_end().len() != hash.len() {
+		return Err(UpdateError::InvalidHash(ParseErrorKind::TrailingWhitespace));
+	}
+	parse_hash(hash)
+		.map(Hash::into_owned)
+		.map_err(|(_, kind)| UpdateError::InvalidHash(kind))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{load, load_with_diagnostics, try_load, LdapScheme, PlaintextPolicy};
+
+	static DATA: &str = "user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00
+crypt_test:bGVh02xkuGli2";
+
+	#[test]
+	fn set_password_adds_and_updates() {
+		let mut htpasswd = try_load(DATA).unwrap();
+		let params = Params {
+			bcrypt_cost: 4,
+			..Params::default()
+		};
+		htpasswd
+			.set_password_with_params("user", "new password", Scheme::BCrypt, &params)
+			.unwrap();
+		htpasswd
+			.set_password("ldap", "password", Scheme::Ldap(LdapScheme::Ssha))
+			.unwrap();
+		assert!(htpasswd.check("user", "new password"));
+		assert!(!htpasswd.check("user", "password"));
+		assert!(htpasswd.check("ldap", "password"));
+		assert_eq!(
+			htpasswd.set_password("a:b", "password", Scheme::Apr1),
+			Err(UpdateError::InvalidUsername)
+		);
+		assert_eq!(
+			htpasswd.set_password_with_params(
+				"user",
+				"password",
+				Scheme::BCrypt,
+				&Params {
+					bcrypt_cost: 3,
+					..Params::default()
+				}
+			),
+			Err(UpdateError::Generate(crate::GenerateError::InvalidParams))
+		);
+	}
+
+	#[test]
+	fn insert_hash_validates() {
+		let mut htpasswd = try_load(DATA).unwrap();
+		htpasswd
+			.insert_hash("sha1", "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=")
+			.unwrap();
+		assert!(htpasswd.check("sha1", "password"));
+		assert_eq!(
+			htpasswd.insert_hash("sha1", "$apr1$lZL6"),
+			Err(UpdateError::InvalidHash(ParseErrorKind::TruncatedApr1Salt))
+		);
+		assert_eq!(
+			htpasswd.insert_hash("sha1", "bGVh02xkuGli2\nroot:x"),
+			Err(UpdateError::LineBreak)
+		);
+		assert_eq!(
+			htpasswd.insert_hash("u", "bGVh02xkuGli2 "),
+			Err(UpdateError::InvalidHash(ParseErrorKind::TrailingWhitespace))
+		);
+		assert_eq!(
+			htpasswd.insert_hash("", "bGVh02xkuGli2"),
+			Err(UpdateError::InvalidUsername)
+		);
+		assert_eq!(
+			htpasswd.insert_hash("#admin", "bGVh02xkuGli2"),
+			Err(UpdateError::InvalidUsername)
+		);
+		assert_eq!(
+			htpasswd.set_password("#admin", "password", Scheme::Apr1),
+			Err(UpdateError::InvalidUsername)
+		);
+		assert_eq!(
+			htpasswd.rename_user("sha1", "#sha1"),
+			Err(UpdateError::InvalidUsername)
+		);
+		htpasswd.insert_hash("ad#min", "bGVh02xkuGli2").unwrap();
+		assert!(htpasswd.check("ad#min", "password"));
+		assert!(htpasswd.check("sha1", "password"));
+	}
+
+	#[test]
+	fn remove_and_rename() {
+		let mut htpasswd = try_load(DATA).unwrap();
+		assert!(htpasswd.remove_user("crypt_test").is_some());
+		assert!(htpasswd.remove_user("crypt_test").is_none());
+		assert_eq!(
+			htpasswd.rename_user("nobody", "somebody"),
+			Err(UpdateError::UnknownUser)
+		);
+		htpasswd.rename_user("user", "renamed").unwrap();
+		assert!(htpasswd.check("renamed", "password"));
+		assert!(!htpasswd.0.contains_key("user"));
+		htpasswd.insert_hash("other", "bGVh02xkuGli2").unwrap();
+		assert_eq!(
+			htpasswd.rename_user("other", "renamed"),
+			Err(UpdateError::DuplicateUsername)
+		);
+		assert_eq!(htpasswd.rename_user("renamed", "renamed"), Ok(()));
+	}
+
+	#[test]
+	fn write_round_trip() {
+		let mut htpasswd = try_load(DATA).unwrap();
+		htpasswd
+			.set_password("new", "password", Scheme::Sha512Crypt)
+			.unwrap();
+		let mut written = Vec::new();
+		htpasswd.write_to(&mut written).unwrap();
+		let written = String::from_utf8(written).unwrap();
+		assert_eq!(written, htpasswd.to_string());
+		assert!(written.starts_with("crypt_test:bGVh02xkuGli2\nnew:$6$"));
+
+		let reloaded = try_load(&written).unwrap();
+		assert_eq!(reloaded.0.len(), 3);
+		assert!(reloaded.check("user", "password"));
+		assert!(reloaded.check("new", "password"));
+	}
+
+	#[test]
+	fn plaintext_round_trip() {
+		let mut htpasswd = try_load(DATA).unwrap();
+		assert_eq!(
+			htpasswd.insert_hash("plain", "pass word "),
+			Err(UpdateError::InvalidHash(ParseErrorKind::TrailingWhitespace))
+		);
+		assert_eq!(
+			htpasswd.insert_hash("plain", "pass word\t"),
+			Err(UpdateError::InvalidHash(ParseErrorKind::TrailingWhitespace))
+		);
+		htpasswd.insert_hash("plain", "pass word").unwrap();
+		let written = htpasswd.to_string();
+		let allow = PlaintextPolicy::AllowPlaintext;
+		for reloaded in [
+			load(&written),
+			load_with_diagnostics(&written).0,
+			try_load(&written).unwrap(),
+		]
+		.iter()
+		{
+			assert!(reloaded.check_with_policy("plain", "pass word", allow));
+		}
+	}
+}
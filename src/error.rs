@@ -0,0 +1,314 @@
+use std::fmt;
+
+/// Error returned by [`try_load`](fn.try_load.html) when an htpasswd entry can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+	/// 1-based line number of the offending entry
+	pub line: usize,
+	/// 1-based byte column at which the problem was detected
+	pub column: usize,
+	/// Username of the offending entry, if the line got far enough to have one
+	pub username: Option<String>,
+	pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+	/// Line has no `:` separating the username from the hash
+	MissingSeparator,
+	/// Nothing before the `:`
+	EmptyUsername,
+	/// Username was already defined on an earlier line
+	DuplicateUsername,
+	/// `$apr1$` or `$1$` entry without a salt of at most 8 characters terminated by `$`
+	TruncatedApr1Salt,
+	/// `$apr1$` or `$1$` digest isn't 22 characters of the crypt alphabet
+	InvalidApr1Hash,
+	/// `{SHA}` digest isn't valid base64
+	InvalidSha1Base64,
+	/// `{SHA}` digest doesn't decode to 20 bytes
+	InvalidSha1Length,
+	/// `rounds=` of a SHA-crypt hash isn't a number between 1000 and 999,999,999 like glibc
+	/// allows. Hashes above [`MAX_SHA_CRYPT_ROUNDS`](constant.MAX_SHA_CRYPT_ROUNDS.html) parse
+	/// but don't verify.
+	MalformedShaCryptRounds,
+	/// SHA-crypt entry without a salt terminated by `$`. Only the first 16 characters of the
+	/// salt are used, like glibc does.
+	TruncatedShaCryptSalt,
+	/// SHA-crypt digest has the wrong length or isn't in the crypt alphabet
+	InvalidShaCryptHash,
+	/// Digest of an LDAP style `{SCHEME}` hash isn't valid base64
+	InvalidLdapBase64,
+	/// Digest of an LDAP style hash has the wrong length, or salted scheme has no salt
+	InvalidLdapDigestLength,
+	/// Argon2 or scrypt hash isn't a PHC string with a digest and valid parameters
+	MalformedPhc,
+	/// bcrypt cost isn't a two digit number between 04 and 31
+	MalformedBcryptCost,
+	/// bcrypt salt and digest aren't 53 characters of the bcrypt alphabet
+	MalformedBcryptHash,
+	/// Whitespace after a hash, which would otherwise make it count as a plaintext password
+	TrailingWhitespace,
+	/// htdigest entry has no `:` between the realm and the digest
+	MissingRealmSeparator,
+	/// htdigest digest isn't 32 hex digits
+	InvalidHtdigestHa1,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"line {}, column {}: {}",
+			self.line, self.column, self.kind
+		)?;
+		if let Some(username) = &self.username {
+			write!(f, " (user {:?})", username)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ParseErrorKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			ParseErrorKind::MissingSeparator => "missing `:` separator",
+			ParseErrorKind::EmptyUsername => "empty username",
+			ParseErrorKind::DuplicateUsername => "duplicate username",
+			ParseErrorKind::TruncatedApr1Salt => "truncated apr1 salt",
+			ParseErrorKind::InvalidApr1Hash => "invalid apr1 digest",
+			ParseErrorKind::InvalidSha1Base64 => "invalid base64 in SHA1 digest",
+			ParseErrorKind::InvalidSha1Length => "SHA1 digest has wrong length",
+			ParseErrorKind::MalformedShaCryptRounds => "malformed SHA-crypt rounds",
+			ParseErrorKind::TruncatedShaCryptSalt => "truncated SHA-crypt salt",
+			ParseErrorKind::InvalidShaCryptHash => "invalid SHA-crypt digest",
+			ParseErrorKind::InvalidLdapBase64 => "invalid base64 in LDAP digest",
+			ParseErrorKind::InvalidLdapDigestLength => "LDAP digest has wrong length",
+			ParseErrorKind::MalformedPhc => "malformed PHC string",
+			ParseErrorKind::MalformedBcryptCost => "malformed bcrypt cost",
+			ParseErrorKind::MalformedBcryptHash => "malformed bcrypt hash",
+			ParseErrorKind::TrailingWhitespace => "trailing whitespace after hash",
+			ParseErrorKind::MissingRealmSeparator => "missing `:` after realm",
+			ParseErrorKind::InvalidHtdigestHa1 => "htdigest digest isn't 32 hex digits",
+		})
+	}
+}
+
+impl std::error::Error for ParseErrorKind {}
+
+/// Error returned when a password can't be verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+	/// No entry for the username
+	UnknownUser,
+	/// Stored hash of a supported scheme is malformed, e.g. a bcrypt hash with a wrong length
+	MalformedHash,
+	/// Stored hash is in a format this crate doesn't support
+	UnsupportedFormat,
+	/// Stored password is plaintext and plaintext isn't allowed
+	PlaintextRejected,
+	/// Stored hash asks for more work than allowed, like a SHA-crypt hash with more than
+	/// [`MAX_SHA_CRYPT_ROUNDS`](constant.MAX_SHA_CRYPT_ROUNDS.html)
+	CostTooHigh,
+}
+
+impl fmt::Display for VerifyError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			VerifyError::UnknownUser => "unknown user",
+			VerifyError::MalformedHash => "malformed password hash",
+			VerifyError::UnsupportedFormat => "unsupported password hash format",
+			VerifyError::PlaintextRejected => "plaintext passwords are not allowed",
+			VerifyError::CostTooHigh => "password hash cost is above the limit",
+		})
+	}
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Error returned when an HTTP `Authorization` header doesn't authenticate a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+	/// Header doesn't use the `Basic` scheme
+	UnsupportedScheme,
+	/// Credentials aren't base64, or have no `:` between username and password, or contain
+	/// control characters
+	Malformed,
+	/// Credentials aren't valid UTF-8
+	InvalidEncoding,
+	/// Header or username is longer than the limit
+	TooLong,
+	/// Unknown user or wrong password
+	InvalidCredentials,
+	/// User's hash can't be used
+	Verify(VerifyError),
+}
+
+impl fmt::Display for AuthError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AuthError::UnsupportedScheme => f.write_str("authorization scheme isn't Basic"),
+			AuthError::Malformed => f.write_str("malformed Basic credentials"),
+			AuthError::InvalidEncoding => f.write_str("Basic credentials aren't UTF-8"),
+			AuthError::TooLong => f.write_str("Basic credentials are too long"),
+			AuthError::InvalidCredentials => f.write_str("invalid username or password"),
+			AuthError::Verify(err) => err.fmt(f),
+		}
+	}
+}
+
+impl std::error::Error for AuthError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			AuthError::Verify(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+/// Error returned when a password hash can't be generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenerateError {
+	/// Cost, memory or other parameters are out of the range supported by the scheme
+	InvalidParams,
+}
+
+impl fmt::Display for GenerateError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			GenerateError::InvalidParams => "invalid hash parameters",
+		})
+	}
+}
+
+impl std::error::Error for GenerateError {}
+
+/// Error returned when an [`Htpasswd`](struct.Htpasswd.html) can't be changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateError {
+	/// Username is empty, starts with a `#` or contains a `:` or a line break
+	InvalidUsername,
+	/// No entry for the username
+	UnknownUser,
+	/// Realm is empty or contains `:` or a line break
+	InvalidRealm,
+	/// Another entry already uses the username
+	DuplicateUsername,
+	/// Hash contains a line break, which would split the entry
+	LineBreak,
+	/// Hash is in a format that can't be parsed
+	InvalidHash(ParseErrorKind),
+	/// Password couldn't be hashed
+	Generate(GenerateError),
+}
+
+impl fmt::Display for UpdateError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			UpdateError::InvalidUsername => f.write_str("invalid username"),
+			UpdateError::UnknownUser => f.write_str("unknown user"),
+			UpdateError::InvalidRealm => f.write_str("invalid realm"),
+			UpdateError::DuplicateUsername => f.write_str("username already exists"),
+			UpdateError::LineBreak => f.write_str("line break in password hash"),
+			UpdateError::InvalidHash(kind) => write!(f, "invalid password hash, {}", kind),
+			UpdateError::Generate(err) => err.fmt(f),
+		}
+	}
+}
+
+impl std::error::Error for UpdateError {}
+
+impl From<GenerateError> for UpdateError {
+	fn from(err: GenerateError) -> Self {
+		UpdateError::Generate(err)
+	}
+}
+
+/// Error returned when an HTTP `Authorization: Digest` header doesn't authenticate a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestError {
+	/// Header doesn't use the `Digest` scheme
+	UnsupportedScheme,
+	/// Header isn't a list of parameters, or lacks a required one
+	Malformed,
+	/// Algorithm other than `MD5` and `SHA-256`, `qop` other than `auth`, or `userhash`
+	Unsupported,
+	/// Credentials are for another realm than the one asked for
+	WrongRealm,
+	/// `uri` parameter isn't the target of the request
+	UriMismatch,
+	/// Unknown user or wrong response
+	InvalidCredentials,
+	/// Nonce was never issued or has expired, the client should retry with a new one
+	StaleNonce,
+	/// Nonce count wasn't higher than in the last accepted request with the nonce
+	ReplayedNonce,
+}
+
+impl fmt::Display for DigestError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			DigestError::UnsupportedScheme => "authorization scheme isn't Digest",
+			DigestError::Malformed => "malformed Digest credentials",
+			DigestError::Unsupported => "unsupported Digest algorithm or qop",
+			DigestError::WrongRealm => "Digest credentials are for another realm",
+			DigestError::UriMismatch => "Digest uri doesn't match the request",
+			DigestError::InvalidCredentials => "invalid username or response",
+			DigestError::StaleNonce => "stale Digest nonce",
+			DigestError::ReplayedNonce => "replayed Digest nonce count",
+		})
+	}
+}
+
+impl std::error::Error for DigestError {}
+
+/// Error returned by [`check_async`](struct.Htpasswd.html#method.check_async) when the
+/// password couldn't be verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolError {
+	/// Pool has as many verifications running and waiting as it allows
+	Overloaded,
+	/// Tokio runtime is shutting down
+	ShutDown,
+}
+
+impl fmt::Display for PoolError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			PoolError::Overloaded => "too many password verifications in progress",
+			PoolError::ShutDown => "runtime is shutting down",
+		})
+	}
+}
+
+impl std::error::Error for PoolError {}
+
+/// Error that kept a changed file from being loaded by
+/// [`HtpasswdWatcher`](struct.HtpasswdWatcher.html)
+#[derive(Debug)]
+pub enum ReloadError {
+	/// File couldn't be read
+	Io(std::io::Error),
+	/// File has a malformed entry
+	Parse(ParseError),
+}
+
+impl fmt::Display for ReloadError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ReloadError::Io(err) => write!(f, "failed to read htpasswd file, {}", err),
+			ReloadError::Parse(err) => write!(f, "failed to parse htpasswd file, {}", err),
+		}
+	}
+}
+
+impl std::error::Error for ReloadError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			ReloadError::Io(err) => Some(err),
+			ReloadError::Parse(err) => Some(err),
+		}
+	}
+}
@@ -0,0 +1,486 @@
+use crate::HtpasswdDocument;
+use password_hash::rand_core::{OsRng, RngCore};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// How many random temporary file names are tried before giving up
+const TEMP_ATTEMPTS: usize = 16;
+
+/// htpasswd file opened for editing
+///
+/// Holds an exclusive advisory lock from [`open`](#method.open) until it's dropped, so
+/// concurrent editors wait for each other instead of overwriting each other's changes.
+/// [`read`](#method.read) takes a shared lock. The lock is taken on a `<file name>.lock` file
+/// next to the htpasswd file, since [`save`](#method.save) replaces the file itself.
+/// `read` only opens the lock file for reading and doesn't create it, so it works in
+/// directories that are read-only to the caller.
+///
+/// If the path is a symlink it's resolved first, so the lock file, the temporary file and the
+/// saved file are all next to the file the link points to, and the link is left in place.
+///
+/// Only available with the `file` feature, which needs Rust 1.89.
+///
+/// ```no_run
+/// use htpasswd_verify::{HtpasswdFile, Scheme};
+///
+/// let mut file = HtpasswdFile::open("/etc/apache2/.htpasswd")?;
+/// file.document_mut().set_password("user", "password", Scheme::BCrypt).unwrap();
+/// file.save()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct HtpasswdFile {
+	path: PathBuf,
+	document: HtpasswdDocument<'static>,
+	_lock: File,
+}
+
+impl HtpasswdFile {
+	/// Locks and reads the file, blocking while another process has it locked.
+	///
+	/// A missing file is treated as empty and created by [`save`](#method.save).
+	pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+		let path = resolve(path.as_ref())?;
+		let lock = open_lock_file(&path)?;
+		lock.lock()?;
+		let data = match fs::read_to_string(&path) {
+			Ok(data) => data,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+			Err(err) => return Err(err),
+		};
+		Ok(HtpasswdFile {
+			path,
+			document: HtpasswdDocument::parse(&data).into_owned(),
+			_lock: lock,
+		})
+	}
+
+	/// Reads the file under a shared lock, waiting for editors to finish saving.
+	///
+	/// Without a lock file the file was never edited through [`open`](#method.open), and it's
+	/// read without a lock. Saves replace the file atomically, so that still never sees a
+	/// partial write.
+	pub fn read(path: impl AsRef<Path>) -> io::Result<HtpasswdDocument<'static>> {
+		let path = &resolve(path.as_ref())?;
+		let _lock = match File::open(sibling_path(path, "", ".lock")?) {
+			Ok(lock) => {
+				lock.lock_shared()?;
+				Some(lock)
+			}
+			Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+			Err(err) => return Err(err),
+		};
+		let data = fs::read_to_string(path)?;
+		Ok(HtpasswdDocument::parse(&data).into_owned())
+	}
+
+	/// Path of the file, with symlinks resolved
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	pub fn document(&self) -> &HtpasswdDocument<'static> {
+		&self.document
+	}
+
+	pub fn document_mut(&mut self) -> &mut HtpasswdDocument<'static> {
+		&mut self.document
+	}
+
+	/// Atomically replaces the file with the document.
+	///
+	/// The document is written and fsynced to a new temporary file with a random name in the
+	/// same directory, which gets the permissions and owner of the original file and is then
+	/// renamed over it. Only root can give files to other users, so if the original belongs to
+	/// someone else the new file is owned by the caller, keeping the group if the caller is in it.
+	pub fn save(&mut self) -> io::Result<()> {
+		let original = match fs::metadata(&self.path) {
+			Ok(metadata) => Some(metadata),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+			Err(err) => return Err(err),
+		};
+		// Only readable by us until the original permissions are copied
+		let (temp_path, mut temp) = create_temp(&self.path, original.is_some())?;
+		let result = self.write_temp(&mut temp, original).and_then(|()| {
+			fs::rename(&temp_path, &self.path)?;
+			sync_dir(&self.path)
+		});
+		if result.is_err() {
+			let _ = fs::remove_file(&temp_path);
+		}
+		result
+	}
+
+	fn write_temp(&self, temp: &mut File, original: Option<fs::Metadata>) -> io::Result<()> {
+		if let Some(original) = original {
+			#[cfg(unix)]
+			{
+				use std::os::unix::fs::MetadataExt;
+				let current = temp.metadata()?;
+				copy_owner(
+					temp,
+					(current.uid(), current.gid()),
+					(original.uid(), original.gid()),
+					|file, uid, gid| std::os::unix::fs::fchown(file, uid, gid),
+				)?;
+			}
+			temp.set_permissions(original.permissions())?;
+		}
+		self.document.write_to(&mut *temp)?;
+		temp.flush()?;
+		temp.sync_all()
+	}
+}
+
+/// Changes the owner of `temp` from `current` to `original`, falling back to only the group,
+/// then to leaving both, when that isn't permitted
+#[cfg(unix)]
+fn copy_owner(
+	temp: &File,
+	current: (u32, u32),
+	original: (u32, u32),
+	chown: impl Fn(&File, Option<u32>, Option<u32>) -> io::Result<()>,
+) -> io::Result<()> {
+	if current == original {
+		return Ok(());
+	}
+	let (uid, gid) = original;
+	let permitted = |result: io::Result<()>| match result {
+		Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Ok(false),
+		result => result.map(|()| true),
+	};
+	if permitted(chown(temp, Some(uid), Some(gid)))? || current.1 == gid {
+		return Ok(());
+	}
+	permitted(chown(temp, None, Some(gid))).map(drop)
+}
+
+/// Path with symlinks resolved, or as given if the file doesn't exist yet
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+	match fs::canonicalize(path) {
+		Ok(path) => Ok(path),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(path.to_path_buf()),
+		Err(err) => Err(err),
+	}
+}
+
+/// Creates a temporary file next to `path` under a random name, never opening an existing file
+/// or following a symlink planted at that name
+fn create_temp(path: &Path, private: bool) -> io::Result<(PathBuf, File)> {
+	create_temp_with(path, private, || format!(".{:016x}.tmp", OsRng.next_u64()))
+}
+
+fn create_temp_with(
+	path: &Path,
+	private: bool,
+	mut suffix: impl FnMut() -> String,
+) -> io::Result<(PathBuf, File)> {
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(if private { 0o600 } else { 0o666 });
+	}
+	#[cfg(not(unix))]
+	let _ = private;
+	for _ in 0..TEMP_ATTEMPTS {
+		let temp_path = sibling_path(path, ".", &suffix())?;
+		match options.open(&temp_path) {
+			Ok(temp) => return Ok((temp_path, temp)),
+			Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+			Err(err) => return Err(err),
+		}
+	}
+	Err(io::Error::new(
+		io::ErrorKind::AlreadyExists,
+		"no unused temporary file name",
+	))
+}
+
+fn open_lock_file(path: &Path) -> io::Result<File> {
+	OpenOptions::new()
+		.read(true)
+		.write(true)
+		.create(true)
+		.truncate(false)
+		.open(sibling_path(path, "", ".lock")?)
+}
+
+/// Path in the same directory as `path`, with the file name wrapped in `prefix` and `suffix`
+fn sibling_path(path: &Path, prefix: &str, suffix: &str) -> io::Result<PathBuf> {
+	let file_name = path
+		.file_name()
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+	let mut name = std::ffi::OsString::from(prefix);
+	name.push(file_name);
+	name.push(suffix);
+	Ok(path.with_file_name(name))
+}
+
+/// Makes the rename durable by syncing the directory entry
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+	let dir = match path.parent() {
+		Some(parent) if !parent.as_os_str().is_empty() => parent,
+		_ => Path::new("."),
+	};
+	File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::test_dir;
+	use std::sync::{Arc, Barrier};
+
+	static DATA: &str = "# team a
+user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00
+crypt_test:bGVh02xkuGli2
+";
+
+	#[test]
+	fn save_replaces_file() {
+		let dir = test_dir("save");
+		let path = dir.join(".htpasswd");
+		fs::write(&path, DATA).unwrap();
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+		}
+
+		let mut file = HtpasswdFile::open(&path).unwrap();
+		file.document_mut()
+			.insert_hash("crypt_test", "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=")
+			.unwrap();
+		file.save().unwrap();
+		drop(file);
+
+		assert_eq!(
+			fs::read_to_string(&path).unwrap(),
+			DATA.replace("bGVh02xkuGli2", "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=")
+		);
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = fs::metadata(&path).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o640);
+		}
+		let mut names = fs::read_dir(&dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.collect::<Vec<_>>();
+		names.sort();
+		assert_eq!(names, [".htpasswd", ".htpasswd.lock"]);
+		assert!(HtpasswdFile::read(&path)
+			.unwrap()
+			.check("crypt_test", "password"));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn create_temp_never_follows_existing_name() {
+		let dir = test_dir("planted");
+		let path = dir.join("htpasswd");
+		let victim = dir.join("victim");
+		fs::write(&victim, "untouched").unwrap();
+		std::os::unix::fs::symlink(&victim, dir.join(".htpasswd.taken.tmp")).unwrap();
+
+		let mut suffixes = vec![".fresh.tmp", ".taken.tmp"];
+		let (temp_path, mut temp) =
+			create_temp_with(&path, true, || suffixes.pop().unwrap().to_string()).unwrap();
+		assert_eq!(temp_path, dir.join(".htpasswd.fresh.tmp"));
+		temp.write_all(b"written").unwrap();
+		assert_eq!(fs::read_to_string(&victim).unwrap(), "untouched");
+		assert_eq!(fs::read_to_string(&temp_path).unwrap(), "written");
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn save_leaves_no_temp_file() {
+		let dir = test_dir("no-temp");
+		let path = dir.join("htpasswd");
+		fs::write(&path, DATA).unwrap();
+		let mut file = HtpasswdFile::open(&path).unwrap();
+		for idx in 0..3 {
+			file.document_mut()
+				.insert_hash(&format!("user{}", idx), "bGVh02xkuGli2")
+				.unwrap();
+			file.save().unwrap();
+		}
+		drop(file);
+		let mut names = fs::read_dir(&dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.collect::<Vec<_>>();
+		names.sort();
+		assert_eq!(names, ["htpasswd", "htpasswd.lock"]);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn save_through_symlink_replaces_target() {
+		let dir = test_dir("symlink");
+		let target_dir = dir.join("conf");
+		fs::create_dir(&target_dir).unwrap();
+		let target = target_dir.join("htpasswd");
+		let link = dir.join("htpasswd");
+		fs::write(&target, DATA).unwrap();
+		std::os::unix::fs::symlink(&target, &link).unwrap();
+
+		let mut file = HtpasswdFile::open(&link).unwrap();
+		assert_eq!(file.path(), fs::canonicalize(&target).unwrap());
+		file.document_mut()
+			.insert_hash("new", "bGVh02xkuGli2")
+			.unwrap();
+		file.save().unwrap();
+		drop(file);
+
+		assert!(fs::symlink_metadata(&link)
+			.unwrap()
+			.file_type()
+			.is_symlink());
+		assert!(HtpasswdFile::read(&target)
+			.unwrap()
+			.check("new", "password"));
+		assert!(HtpasswdFile::read(&link).unwrap().check("new", "password"));
+		assert!(target_dir.join("htpasswd.lock").exists());
+		assert!(!dir.join("htpasswd.lock").exists());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn save_creates_missing_file() {
+		let dir = test_dir("create");
+		let path = dir.join("htpasswd");
+		assert!(HtpasswdFile::read(&path).is_err());
+		let mut file = HtpasswdFile::open(&path).unwrap();
+		file.document_mut()
+			.insert_hash("user", "bGVh02xkuGli2")
+			.unwrap();
+		file.save().unwrap();
+		assert_eq!(fs::read_to_string(&path).unwrap(), "user:bGVh02xkuGli2\n");
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn read_works_in_read_only_directory() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = test_dir("read-only");
+		let path = dir.join("htpasswd");
+		fs::write(&path, DATA).unwrap();
+		let set_mode = |path: &Path, mode| {
+			fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap()
+		};
+		set_mode(&dir, 0o555);
+		assert!(HtpasswdFile::read(&path)
+			.unwrap()
+			.check("crypt_test", "password"));
+		assert!(!dir.join("htpasswd.lock").exists());
+
+		// An existing lock file is only opened for reading
+		set_mode(&dir, 0o755);
+		drop(HtpasswdFile::open(&path).unwrap());
+		set_mode(&dir.join("htpasswd.lock"), 0o444);
+		set_mode(&dir, 0o555);
+		assert!(HtpasswdFile::read(&path)
+			.unwrap()
+			.check("crypt_test", "password"));
+		set_mode(&dir, 0o755);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn open_locks_out_other_editors() {
+		let dir = test_dir("lock");
+		let path = dir.join("htpasswd");
+		fs::write(&path, DATA).unwrap();
+		let lock_path = dir.join("htpasswd.lock");
+
+		let file = HtpasswdFile::open(&path).unwrap();
+		let lock = File::open(&lock_path).unwrap();
+		assert!(lock.try_lock_shared().is_err());
+		drop(file);
+		assert!(lock.try_lock_shared().is_ok());
+		assert!(HtpasswdFile::read(&path).is_ok());
+		lock.unlock().unwrap();
+
+		let threads = 4;
+		let barrier = Arc::new(Barrier::new(threads));
+		let handles = (0..threads)
+			.map(|idx| {
+				let path = path.clone();
+				let barrier = Arc::clone(&barrier);
+				std::thread::spawn(move || {
+					barrier.wait();
+					let mut file = HtpasswdFile::open(&path).unwrap();
+					file.document_mut()
+						.insert_hash(&format!("user{}", idx), "bGVh02xkuGli2")
+						.unwrap();
+					file.save().unwrap();
+				})
+			})
+			.collect::<Vec<_>>();
+		for handle in handles {
+			handle.join().unwrap();
+		}
+		let document = HtpasswdFile::read(&path).unwrap();
+		for idx in 0..threads {
+			assert!(document.check(&format!("user{}", idx), "password"));
+		}
+		assert!(document.to_string().starts_with(DATA));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn save_keeps_own_owner_when_chown_is_denied() {
+		use std::cell::RefCell;
+
+		let dir = test_dir("chown");
+		let temp = File::create(dir.join("temp")).unwrap();
+		let calls = RefCell::new(Vec::new());
+		let denied = || Err(io::Error::from(io::ErrorKind::PermissionDenied));
+
+		// Group kept when only the user can't be changed
+		let chown = |_: &File, uid: Option<u32>, gid| {
+			calls.borrow_mut().push((uid, gid));
+			if uid.is_some() {
+				denied()
+			} else {
+				Ok(())
+			}
+		};
+		copy_owner(&temp, (1000, 1000), (0, 33), chown).unwrap();
+		assert_eq!(calls.take(), [(Some(0), Some(33)), (None, Some(33))]);
+
+		// Owner left alone when neither can be changed
+		let chown = |_: &File, uid: Option<u32>, gid| {
+			calls.borrow_mut().push((uid, gid));
+			denied()
+		};
+		copy_owner(&temp, (1000, 1000), (0, 33), chown).unwrap();
+		assert_eq!(calls.take(), [(Some(0), Some(33)), (None, Some(33))]);
+		copy_owner(&temp, (1000, 33), (0, 33), chown).unwrap();
+		assert_eq!(calls.take(), [(Some(0), Some(33))]);
+		copy_owner(&temp, (0, 33), (0, 33), chown).unwrap();
+		assert!(calls.take().is_empty());
+
+		// Other errors still fail the save
+		let failing = |_: &File, _, _| Err(io::Error::from(io::ErrorKind::Other));
+		assert!(copy_owner(&temp, (1000, 1000), (0, 33), failing).is_err());
+		drop(temp);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
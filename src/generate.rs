@@ -0,0 +1,308 @@
+use crate::md5::{self, APR1_ID, MD5_CRYPT_ID};
+use crate::phc::{self, Argon2Params, ScryptParams};
+use crate::{
+	ldap, BCryptVersion, GenerateError, Hash, LdapScheme, ParseErrorKind, APR1_SALT_MAX_LEN,
+	BASE64, MAX_SHA_CRYPT_ROUNDS, SHA1_ID, SHA_CRYPT_SALT_MAX_LEN,
+};
+use base64::Engine;
+use password_hash::rand_core::{OsRng, RngCore};
+
+/// Alphabet of crypt style salts
+const SALT_CHARS: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const CRYPT_SALT_LEN: usize = 2;
+const LDAP_SALT_LEN: usize = 8;
+const DUMMY_PASSWORD_LEN: usize = 16;
+
+/// Hash format produced by [`Hash::generate`](enum.Hash.html#method.generate)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+	/// Apache `$apr1$` MD5, the `htpasswd -m` default
+	Apr1,
+	/// `$1$` MD5-crypt
+	Md5Crypt,
+	/// `$2y$` bcrypt, like `htpasswd -B`
+	BCrypt,
+	/// Unsalted `{SHA}`
+	SHA1,
+	/// Traditional DES crypt, only the first 8 characters of the password are used
+	Crypt,
+	/// `$5$` SHA-256-crypt
+	Sha256Crypt,
+	/// `$6$` SHA-512-crypt
+	Sha512Crypt,
+	/// `{SSHA}` and the other LDAP style schemes
+	Ldap(LdapScheme),
+	/// Argon2 PHC string
+	Argon2,
+	/// scrypt PHC string
+	Scrypt,
+}
+
+/// Cost parameters for [`Hash::generate`](enum.Hash.html#method.generate), ignored by schemes
+/// they don't apply to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Params {
+	/// bcrypt cost between 4 and 31
+	pub bcrypt_cost: u32,
+	/// SHA-crypt rounds between 1000 and [`MAX_SHA_CRYPT_ROUNDS`](constant.MAX_SHA_CRYPT_ROUNDS.html),
+	/// 5000 without `rounds=` if not set
+	pub sha_crypt_rounds: Option<u32>,
+	pub argon2: Argon2Params,
+	pub scrypt: ScryptParams,
+}
+
+impl Default for Params {
+	fn default() -> Self {
+		Params {
+			bcrypt_cost: bcrypt::DEFAULT_COST,
+			sha_crypt_rounds: None,
+			argon2: Argon2Params::default(),
+			scrypt: ScryptParams::default(),
+		}
+	}
+}
+
+impl Hash<'_> {
+	/// Hashes the password with a random salt, returning the hash in htpasswd format.
+	///
+	/// ```
+	/// use htpasswd_verify::{Hash, Params, Scheme};
+	///
+	/// let params = Params { bcrypt_cost: 5, ..Params::default() };
+	/// let hash = Hash::generate("password", Scheme::BCrypt, &params).unwrap();
+	/// assert!(hash.starts_with("$2y$05$"));
+	/// assert!(Hash::parse(&hash).check("password"));
+	/// ```
+	pub fn generate(
+		password: &str,
+		scheme: Scheme,
+		params: &Params,
+	) -> Result<String, GenerateError> {
+		match scheme {
+			Scheme::Apr1 => {
+				let salt = random_salt(APR1_SALT_MAX_LEN);
+				let hash = md5::md5_crypt_encode(password, &salt, APR1_ID);
+				Ok(md5::format_hash_with(APR1_ID, &hash, &salt))
+			}
+			Scheme::Md5Crypt => {
+				let salt = random_salt(APR1_SALT_MAX_LEN);
+				let hash = md5::md5_crypt_encode(password, &salt, MD5_CRYPT_ID);
+				Ok(md5::format_hash_with(MD5_CRYPT_ID, &hash, &salt))
+			}
+			Scheme::BCrypt => bcrypt::hash_with_result(password, params.bcrypt_cost)
+				.map(|hash| hash.format_for_version(BCryptVersion::TwoY.into()))
+				.map_err(|_| GenerateError::InvalidParams),
+			Scheme::SHA1 => Ok(format!(
+				"{}{}",
+				SHA1_ID,
+				BASE64.encode(crate::sha1_digest(password))
+			)),
+			#[allow(deprecated)]
+			Scheme::Crypt => pwhash::unix_crypt::hash_with(&random_salt(CRYPT_SALT_LEN), password)
+				.map_err(|_| GenerateError::InvalidParams),
+			Scheme::Sha256Crypt => {
+				let salt = random_salt(SHA_CRYPT_SALT_MAX_LEN);
+				crate::sha256_crypt_hash_with(sha_crypt_setup(&salt, params)?, password)
+					.map_err(|_| GenerateError::InvalidParams)
+			}
+			Scheme::Sha512Crypt => {
+				let salt = random_salt(SHA_CRYPT_SALT_MAX_LEN);
+				pwhash::sha512_crypt::hash_with(sha_crypt_setup(&salt, params)?, password)
+					.map_err(|_| GenerateError::InvalidParams)
+			}
+			Scheme::Ldap(scheme) => {
+				let mut salt = [0u8; LDAP_SALT_LEN];
+				OsRng.fill_bytes(&mut salt);
+				Ok(ldap::generate(scheme, password, &salt))
+			}
+			Scheme::Argon2 => phc::hash_argon2(password, &params.argon2),
+			Scheme::Scrypt => phc::hash_scrypt(password, &params.scrypt),
+		}
+	}
+
+	/// How to make a dummy hash that takes as long to verify, without anything of this hash
+	pub(crate) fn dummy_spec(&self) -> Option<DummySpec> {
+		let mut params = Params::default();
+		let scheme = match self {
+			Hash::MD5(_) => Scheme::Apr1,
+			Hash::Md5Crypt(_) => Scheme::Md5Crypt,
+			Hash::BCrypt(hash) => {
+				params.bcrypt_cost = hash.cost;
+				Scheme::BCrypt
+			}
+			Hash::SHA1(_) => Scheme::SHA1,
+			Hash::Crypt(_) => Scheme::Crypt,
+			Hash::Sha256Crypt(hash) => {
+				params.sha_crypt_rounds = hash.rounds;
+				Scheme::Sha256Crypt
+			}
+			Hash::Sha512Crypt(hash) => {
+				params.sha_crypt_rounds = hash.rounds;
+				Scheme::Sha512Crypt
+			}
+			Hash::Ldap(hash) => Scheme::Ldap(hash.scheme),
+			Hash::LdapCrypt(hash) => return hash.dummy_spec(),
+			Hash::Argon2(hash) => {
+				params.argon2 = phc::argon2_hash_params(hash)?;
+				Scheme::Argon2
+			}
+			Hash::Scrypt(hash) => {
+				params.scrypt = phc::scrypt_hash_params(hash)?;
+				Scheme::Scrypt
+			}
+			Hash::Plaintext(_) => return Some(DummySpec::Plaintext),
+			Hash::Unknown(_) => return Some(DummySpec::Unknown),
+			Hash::Malformed(_, kind) => return Some(DummySpec::Malformed(*kind)),
+		};
+		Some(DummySpec::Generated(scheme, params))
+	}
+}
+
+/// Scheme and cost of a dummy hash, see `Hash::dummy_spec`
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum DummySpec {
+	Generated(Scheme, Params),
+	Plaintext,
+	Unknown,
+	Malformed(ParseErrorKind),
+}
+
+impl DummySpec {
+	/// Hash of a random password, which can take as long to generate as a verification
+	pub(crate) fn generate(self) -> Option<Hash<'static>> {
+		let password = random_salt(DUMMY_PASSWORD_LEN);
+		match self {
+			DummySpec::Generated(scheme, params) => {
+				let hash = Hash::generate(&password, scheme, &params).ok()?;
+				Hash::try_parse(&hash).ok().map(Hash::into_owned)
+			}
+			DummySpec::Plaintext => Some(Hash::Plaintext(password.into())),
+			DummySpec::Unknown => Some(Hash::Unknown("".into())),
+			DummySpec::Malformed(kind) => Some(Hash::Malformed("".into(), kind)),
+		}
+	}
+}
+
+impl From<BCryptVersion> for bcrypt::Version {
+	fn from(version: BCryptVersion) -> Self {
+		match version {
+			BCryptVersion::TwoA => bcrypt::Version::TwoA,
+			BCryptVersion::TwoB => bcrypt::Version::TwoB,
+			BCryptVersion::TwoX => bcrypt::Version::TwoX,
+			BCryptVersion::TwoY => bcrypt::Version::TwoY,
+		}
+	}
+}
+
+fn sha_crypt_setup<'a>(
+	salt: &'a str,
+	params: &Params,
+) -> Result<pwhash::HashSetup<'a>, GenerateError> {
+	let valid_rounds = pwhash::sha256_crypt::MIN_ROUNDS..=MAX_SHA_CRYPT_ROUNDS;
+	if let Some(rounds) = params.sha_crypt_rounds {
+		if !valid_rounds.contains(&rounds) {
+			return Err(GenerateError::InvalidParams);
+		}
+	}
+	Ok(pwhash::HashSetup {
+		salt: Some(salt),
+		rounds: params.sha_crypt_rounds,
+	})
+}
+
+/// Random salt of `len` characters from the crypt alphabet
+fn random_salt(len: usize) -> String {
+	let mut bytes = vec![0u8; len];
+	OsRng.fill_bytes(&mut bytes);
+	bytes
+		.iter()
+		.map(|b| SALT_CHARS[(b & 0x3f) as usize] as char)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::phc::Argon2Variant;
+
+	fn test_params() -> Params {
+		Params {
+			bcrypt_cost: 4,
+			sha_crypt_rounds: Some(1000),
+			argon2: Argon2Params {
+				variant: Argon2Variant::Argon2id,
+				memory: 256,
+				iterations: 1,
+				parallelism: 1,
+			},
+			scrypt: ScryptParams {
+				log_n: 4,
+				block_size: 8,
+				parallelism: 1,
+			},
+		}
+	}
+
+	#[test]
+	fn generate_every_scheme() {
+		let schemes = [
+			Scheme::Apr1,
+			Scheme::Md5Crypt,
+			Scheme::BCrypt,
+			Scheme::SHA1,
+			Scheme::Crypt,
+			Scheme::Sha256Crypt,
+			Scheme::Sha512Crypt,
+			Scheme::Ldap(LdapScheme::Ssha),
+			Scheme::Ldap(LdapScheme::Sha256),
+			Scheme::Ldap(LdapScheme::Ssha512),
+			Scheme::Ldap(LdapScheme::Smd5),
+			Scheme::Argon2,
+			Scheme::Scrypt,
+		];
+		for &scheme in schemes.iter() {
+			let hash = Hash::generate("password", scheme, &test_params()).unwrap();
+			let parsed = Hash::try_parse(&hash).unwrap();
+			assert!(!matches!(parsed, Hash::Plaintext(_) | Hash::Unknown(_)));
+			assert_eq!(parsed.verify("password"), Ok(true), "{:?} {}", scheme, hash);
+			assert_eq!(
+				parsed.verify("passwort"),
+				Ok(false),
+				"{:?} {}",
+				scheme,
+				hash
+			);
+		}
+	}
+
+	#[test]
+	fn generate_random_salt() {
+		let first = Hash::generate("password", Scheme::Apr1, &test_params()).unwrap();
+		let second = Hash::generate("password", Scheme::Apr1, &test_params()).unwrap();
+		assert_ne!(first, second);
+		assert!(first.starts_with("$apr1$"));
+	}
+
+	#[test]
+	fn generate_invalid_params() {
+		let params = Params {
+			bcrypt_cost: 3,
+			sha_crypt_rounds: Some(999),
+			..test_params()
+		};
+		for &scheme in [Scheme::BCrypt, Scheme::Sha256Crypt, Scheme::Sha512Crypt].iter() {
+			assert_eq!(
+				Hash::generate("password", scheme, &params),
+				Err(GenerateError::InvalidParams)
+			);
+		}
+		let params = Params {
+			sha_crypt_rounds: Some(MAX_SHA_CRYPT_ROUNDS + 1),
+			..test_params()
+		};
+		assert_eq!(
+			Hash::generate("password", Scheme::Sha256Crypt, &params),
+			Err(GenerateError::InvalidParams)
+		);
+	}
+}
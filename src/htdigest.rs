@@ -0,0 +1,746 @@
+use crate::basic::quoted_string;
+use crate::edit::{is_valid_field, validate_username};
+use crate::md5::{Md5, DIGEST_SIZE};
+use crate::{
+	constant_time_eq, entries, AuthenticatedUser, DigestError, ParseError, ParseErrorKind,
+	UpdateError, VerifyError,
+};
+use crypto::{digest::Digest, sha2::Sha256};
+use password_hash::rand_core::{OsRng, RngCore};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// Apache's htdigest file, with an MD5 `HA1` digest per user and realm
+///
+/// Every line is `user:realm:MD5(user:realm:password)`. The same user may appear once per realm.
+///
+/// ```
+/// use htpasswd_verify::Htdigest;
+///
+/// let htdigest = Htdigest::parse("user:Staff:a671186ab0d57af5f1b7601d91d3ef8a").unwrap();
+/// assert!(htdigest.check("user", "Staff", "password"));
+/// assert!(!htdigest.check("user", "Guests", "password"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Htdigest<'a>(pub HashMap<(Cow<'a, str>, Cow<'a, str>), [u8; DIGEST_SIZE]>);
+
+/// Hash function of Digest authentication
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DigestAlgorithm {
+	/// `MD5`, the only algorithm htdigest files store
+	#[default]
+	Md5,
+	/// `SHA-256`
+	Sha256,
+}
+
+impl DigestAlgorithm {
+	/// Name used in the `algorithm` parameter
+	pub fn name(self) -> &'static str {
+		match self {
+			DigestAlgorithm::Md5 => "MD5",
+			DigestAlgorithm::Sha256 => "SHA-256",
+		}
+	}
+
+	/// Lowercase hex digest of the data
+	fn hex_digest(self, data: &str) -> String {
+		match self {
+			DigestAlgorithm::Md5 => Md5::hex_digest(data),
+			DigestAlgorithm::Sha256 => {
+				let mut hasher = Sha256::new();
+				hasher.input_str(data);
+				let digest = hasher.result_str();
+				hasher.reset();
+				digest
+			}
+		}
+	}
+}
+
+/// `HA1` of Digest authentication as lowercase hex, the digest of `user:realm:password`
+///
+/// ```
+/// use htpasswd_verify::{digest_ha1, DigestAlgorithm};
+///
+/// assert_eq!(digest_ha1(DigestAlgorithm::Md5, "user", "Staff", "password"), "a671186ab0d57af5f1b7601d91d3ef8a");
+/// ```
+pub fn digest_ha1(
+	algorithm: DigestAlgorithm,
+	username: &str,
+	realm: &str,
+	password: &str,
+) -> String {
+	let input = Zeroizing::new(format!("{}:{}:{}", username, realm, password));
+	algorithm.hex_digest(&input)
+}
+
+/// Parameters of an `Authorization: Digest` header, as defined by RFC 7616
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestCredentials {
+	pub username: String,
+	pub realm: String,
+	pub nonce: String,
+	pub uri: String,
+	/// Response as sent, hex digest of the algorithm
+	pub response: String,
+	pub algorithm: DigestAlgorithm,
+	pub qop: Option<String>,
+	/// Nonce count as sent, 8 hex digits
+	pub nc: Option<String>,
+	pub cnonce: Option<String>,
+	pub opaque: Option<String>,
+}
+
+impl DigestCredentials {
+	/// Parses the value of an `Authorization: Digest` header
+	///
+	/// The scheme and parameter names are case-insensitive. `-sess` algorithms and `userhash`
+	/// aren't supported.
+	pub fn parse(header: &str) -> Result<Self, DigestError> {
+		let header = header.trim_matches(|c| c == ' ' || c == '\t');
+		let (scheme, params) = header
+			.split_once(' ')
+			.ok_or(DigestError::UnsupportedScheme)?;
+		if !scheme.eq_ignore_ascii_case("Digest") {
+			return Err(DigestError::UnsupportedScheme);
+		}
+		let mut params = parse_params(params).ok_or(DigestError::Malformed)?;
+		let mut required = |name: &str| params.remove(name).ok_or(DigestError::Malformed);
+		let (username, realm, nonce, uri, response) = (
+			required("username")?,
+			required("realm")?,
+			required("nonce")?,
+			required("uri")?,
+			required("response")?,
+		);
+		let algorithm = match params.remove("algorithm") {
+			None => DigestAlgorithm::Md5,
+			Some(name) if name.eq_ignore_ascii_case("MD5") => DigestAlgorithm::Md5,
+			Some(name) if name.eq_ignore_ascii_case("SHA-256") => DigestAlgorithm::Sha256,
+			Some(_) => return Err(DigestError::Unsupported),
+		};
+		if matches!(params.get("userhash"), Some(userhash) if userhash.eq_ignore_ascii_case("true"))
+		{
+			return Err(DigestError::Unsupported);
+		}
+		Ok(DigestCredentials {
+			username,
+			realm,
+			nonce,
+			uri,
+			response,
+			algorithm,
+			qop: params.remove("qop"),
+			nc: params.remove("nc"),
+			cnonce: params.remove("cnonce"),
+			opaque: params.remove("opaque"),
+		})
+	}
+
+	/// Nonce count as a number
+	pub fn nonce_count(&self) -> Result<u32, DigestError> {
+		match &self.nc {
+			Some(nc) if nc.len() == 8 && nc.bytes().all(|b| b.is_ascii_hexdigit()) => {
+				u32::from_str_radix(nc, 16).map_err(|_| DigestError::Malformed)
+			}
+			_ => Err(DigestError::Malformed),
+		}
+	}
+
+	/// Response a client knowing the password would send, computed from the hex `HA1` and the
+	/// method of the request
+	///
+	/// Only `qop=auth` is supported, which requires `nc` and `cnonce`.
+	///
+	/// ```
+	/// use htpasswd_verify::{digest_ha1, DigestCredentials};
+	///
+	/// let credentials = DigestCredentials::parse(r#"Digest username="user", realm="Staff", uri="/", nonce="abc", cnonce="def", nc=00000001, qop=auth, response="""#).unwrap();
+	/// let ha1 = digest_ha1(credentials.algorithm, "user", "Staff", "password");
+	/// assert_eq!(credentials.expected_response(&ha1, "GET").unwrap().len(), 32);
+	/// ```
+	pub fn expected_response(&self, ha1: &str, method: &str) -> Result<String, DigestError> {
+		match &self.qop {
+			Some(qop) if qop == "auth" => {}
+			_ => return Err(DigestError::Unsupported),
+		}
+		let (nc, cnonce) = match (&self.nc, &self.cnonce) {
+			(Some(nc), Some(cnonce)) => (nc, cnonce),
+			_ => return Err(DigestError::Malformed),
+		};
+		let ha2 = self
+			.algorithm
+			.hex_digest(&format!("{}:{}", method, self.uri));
+		Ok(self.algorithm.hex_digest(&format!(
+			"{}:{}:{}:{}:auth:{}",
+			ha1, self.nonce, nc, cnonce, ha2
+		)))
+	}
+}
+
+/// Parses comma separated `name=token` and `name="quoted string"` parameters, with lowercase
+/// names
+fn parse_params(mut input: &str) -> Option<HashMap<String, String>> {
+	let is_space = |c: char| c == ' ' || c == '\t';
+	let mut params = HashMap::new();
+	loop {
+		input = input.trim_start_matches(|c| is_space(c) || c == ',');
+		if input.is_empty() {
+			return Some(params);
+		}
+		let (name, rest) = input.split_once('=')?;
+		let name = name.trim_end_matches(is_space);
+		if name.is_empty() || name.contains(|c: char| is_space(c) || c == ',' || c == '"') {
+			return None;
+		}
+		let rest = rest.trim_start_matches(is_space);
+		let value;
+		if let Some(quoted) = rest.strip_prefix('"') {
+			let mut unquoted = String::new();
+			let mut chars = quoted.char_indices();
+			let end = loop {
+				match chars.next()? {
+					(idx, '"') => break idx,
+					(_, '\\') => unquoted.push(chars.next()?.1),
+					(_, c) => unquoted.push(c),
+				}
+			};
+			value = unquoted;
+			input = &quoted[end + 1..];
+		} else {
+			let end = rest
+				.find(|c: char| is_space(c) || c == ',')
+				.unwrap_or(rest.len());
+			value = rest[..end].to_string();
+			input = &rest[end..];
+		}
+		// Anything but a separator after the value is malformed
+		let after = input.trim_start_matches(is_space);
+		if !after.is_empty() && !after.starts_with(',') {
+			return None;
+		}
+		if params.insert(name.to_ascii_lowercase(), value).is_some() {
+			return None;
+		}
+	}
+}
+
+/// Nonces handed out in Digest challenges, with the highest nonce count seen for each
+///
+/// A nonce is accepted until it's `max_age` old, and each request has to use a higher nonce
+/// count than the last accepted one, so captured requests can't be replayed. At most
+/// `max_nonces` are remembered, the oldest one is forgotten when a new one doesn't fit.
+#[derive(Debug)]
+pub struct DigestNonces {
+	max_age: Duration,
+	max_nonces: usize,
+	nonces: Mutex<HashMap<String, NonceState>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct NonceState {
+	issued: Instant,
+	/// Highest nonce count accepted so far
+	count: u32,
+}
+
+impl DigestNonces {
+	pub fn new(max_age: Duration, max_nonces: usize) -> Self {
+		DigestNonces {
+			max_age,
+			max_nonces,
+			nonces: Mutex::default(),
+		}
+	}
+
+	/// Creates a random nonce and remembers it
+	pub fn issue(&self) -> String {
+		let mut bytes = [0u8; 16];
+		OsRng.fill_bytes(&mut bytes);
+		let nonce = hex(&bytes);
+
+		let mut nonces = self.nonces.lock().unwrap_or_else(PoisonError::into_inner);
+		let max_age = self.max_age;
+		nonces.retain(|_, state| state.issued.elapsed() < max_age);
+		while !nonces.is_empty() && nonces.len() >= self.max_nonces {
+			let oldest = nonces
+				.iter()
+				.min_by_key(|(_, state)| state.issued)
+				.map(|(nonce, _)| nonce.clone())
+				.expect("nonces aren't empty");
+			nonces.remove(&oldest);
+		}
+		let state = NonceState {
+			issued: Instant::now(),
+			count: 0,
+		};
+		nonces.insert(nonce.clone(), state);
+		nonce
+	}
+
+	/// `WWW-Authenticate` challenge with a new nonce for a 401 response
+	///
+	/// `stale` tells the client that its last nonce expired and it can retry without asking the
+	/// user again.
+	///
+	/// ```
+	/// use htpasswd_verify::{DigestAlgorithm, DigestNonces};
+	/// use std::time::Duration;
+	///
+	/// let nonces = DigestNonces::new(Duration::from_secs(300), 1024);
+	/// let challenge = nonces.challenge("Staff", DigestAlgorithm::Md5, false);
+	/// assert!(challenge.starts_with(r#"Digest realm="Staff", qop="auth", algorithm=MD5, nonce=""#));
+	/// ```
+	pub fn challenge(&self, realm: &str, algorithm: DigestAlgorithm, stale: bool) -> String {
+		let mut challenge = format!(
+			"Digest realm={}, qop=\"auth\", algorithm={}, nonce=\"{}\"",
+			quoted_string(realm),
+			algorithm.name(),
+			self.issue()
+		);
+		if stale {
+			challenge.push_str(", stale=true");
+		}
+		challenge
+	}
+
+	/// Records a use of the nonce, rejecting expired or unknown nonces and counts that aren't
+	/// higher than the last one
+	pub fn use_nonce(&self, nonce: &str, count: u32) -> Result<(), DigestError> {
+		let mut nonces = self.nonces.lock().unwrap_or_else(PoisonError::into_inner);
+		let state = match nonces.get_mut(nonce) {
+			Some(state) if state.issued.elapsed() < self.max_age => state,
+			_ => return Err(DigestError::StaleNonce),
+		};
+		if count <= state.count {
+			return Err(DigestError::ReplayedNonce);
+		}
+		state.count = count;
+		Ok(())
+	}
+}
+
+impl<'a> Htdigest<'a> {
+	/// Parses an htdigest file, failing on the first malformed entry
+	///
+	/// Blank lines and lines starting with `#` are skipped like in htpasswd files.
+	pub fn parse(bytes: &'a str) -> Result<Self, ParseError> {
+		let mut entries_by_user = HashMap::new();
+		for (line_no, line) in entries(bytes) {
+			let error = |column: usize, kind| ParseError {
+				line: line_no,
+				column: column + 1,
+				username: line.find(':').map(|sep| line[..sep].to_string()),
+				kind,
+			};
+			let (username, rest) = line
+				.split_once(':')
+				.ok_or_else(|| error(line.len(), ParseErrorKind::MissingSeparator))?;
+			if username.is_empty() {
+				return Err(error(0, ParseErrorKind::EmptyUsername));
+			}
+			let (realm, ha1) = rest
+				.split_once(':')
+				.ok_or_else(|| error(line.len(), ParseErrorKind::MissingRealmSeparator))?;
+			let ha1 = unhex(ha1).ok_or_else(|| {
+				error(
+					username.len() + realm.len() + 2,
+					ParseErrorKind::InvalidHtdigestHa1,
+				)
+			})?;
+			if entries_by_user
+				.insert((username.into(), realm.into()), ha1)
+				.is_some()
+			{
+				return Err(error(0, ParseErrorKind::DuplicateUsername));
+			}
+		}
+		Ok(Htdigest(entries_by_user))
+	}
+
+	/// Stored `HA1` of the user in the realm
+	pub fn ha1(&self, username: &str, realm: &str) -> Option<[u8; DIGEST_SIZE]> {
+		let entries: &HashMap<(Cow<'_, str>, Cow<'_, str>), _> = &self.0;
+		entries
+			.get(&(Cow::Borrowed(username), Cow::Borrowed(realm)))
+			.copied()
+	}
+
+	/// Verifies the user's password in the realm
+	pub fn check(&self, username: &str, realm: &str, password: impl AsRef<str>) -> bool {
+		self.verify(username, realm, password).unwrap_or(false)
+	}
+
+	/// Verifies the user's password in the realm, failing if the user isn't in the realm
+	pub fn verify(
+		&self,
+		username: &str,
+		realm: &str,
+		password: impl AsRef<str>,
+	) -> Result<bool, VerifyError> {
+		let stored = self.ha1(username, realm).ok_or(VerifyError::UnknownUser)?;
+		let ha1 = Zeroizing::new(digest_ha1(
+			DigestAlgorithm::Md5,
+			username,
+			realm,
+			password.as_ref(),
+		));
+		Ok(constant_time_eq(ha1.as_bytes(), hex(&stored).as_bytes()))
+	}
+
+	/// Verifies an `Authorization: Digest` header of a request, as defined by RFC 7616
+	///
+	/// `realm` is the realm the server asked for and `uri` the target of the request, as in
+	/// the request line. Only `MD5` can be verified since that's what htdigest files store,
+	/// and only `qop=auth`. The nonce count is recorded in `nonces` once the response checks
+	/// out.
+	///
+	/// ```
+	/// use htpasswd_verify::{DigestAlgorithm, DigestCredentials, DigestNonces, Htdigest};
+	/// use std::time::Duration;
+	///
+	/// let htdigest = Htdigest::parse("user:Staff:a671186ab0d57af5f1b7601d91d3ef8a").unwrap();
+	/// let nonces = DigestNonces::new(Duration::from_secs(300), 1024);
+	/// let nonce = nonces.issue();
+	///
+	/// // What the client sends
+	/// let credentials = DigestCredentials::parse(&format!(r#"Digest username="user", realm="Staff", uri="/", nonce="{}", cnonce="0a4f113b", nc=00000001, qop=auth, response="""#, nonce)).unwrap();
+	/// let ha1 = htpasswd_verify::digest_ha1(DigestAlgorithm::Md5, "user", "Staff", "password");
+	/// let response = credentials.expected_response(&ha1, "GET").unwrap();
+	/// let header = format!(r#"Digest username="user", realm="Staff", uri="/", nonce="{}", cnonce="0a4f113b", nc=00000001, qop=auth, response="{}""#, nonce, response);
+	///
+	/// let user = htdigest.verify_digest_header(&header, "Staff", "GET", "/", &nonces).unwrap();
+	/// assert_eq!(user.username, "user");
+	/// ```
+	pub fn verify_digest_header(
+		&self,
+		header: &str,
+		realm: &str,
+		method: &str,
+		uri: &str,
+		nonces: &DigestNonces,
+	) -> Result<AuthenticatedUser, DigestError> {
+		let credentials = DigestCredentials::parse(header)?;
+		if credentials.algorithm != DigestAlgorithm::Md5 {
+			return Err(DigestError::Unsupported);
+		}
+		if credentials.realm != realm {
+			return Err(DigestError::WrongRealm);
+		}
+		if credentials.uri != uri {
+			return Err(DigestError::UriMismatch);
+		}
+		let count = credentials.nonce_count()?;
+		let stored = self
+			.ha1(&credentials.username, realm)
+			.ok_or(DigestError::InvalidCredentials)?;
+		let expected = credentials.expected_response(&hex(&stored), method)?;
+		let response = credentials.response.to_ascii_lowercase();
+		if !constant_time_eq(expected.as_bytes(), response.as_bytes()) {
+			return Err(DigestError::InvalidCredentials);
+		}
+		// Counted only for correct responses, so guesses can't use up the nonce
+		nonces.use_nonce(&credentials.nonce, count)?;
+		Ok(AuthenticatedUser {
+			username: credentials.username,
+		})
+	}
+
+	/// Hashes the password and stores it, adding the user to the realm if they aren't in it yet
+	///
+	/// ```
+	/// use htpasswd_verify::Htdigest;
+	///
+	/// let mut htdigest = Htdigest::default();
+	/// htdigest.set_password("user", "Staff", "password").unwrap();
+	/// assert_eq!(htdigest.to_string(), "user:Staff:a671186ab0d57af5f1b7601d91d3ef8a\n");
+	/// ```
+	pub fn set_password(
+		&mut self,
+		username: &str,
+		realm: &str,
+		password: impl AsRef<str>,
+	) -> Result<(), UpdateError> {
+		validate_username(username)?;
+		if !is_valid_field(realm) {
+			return Err(UpdateError::InvalidRealm);
+		}
+		let ha1 = Zeroizing::new(digest_ha1(
+			DigestAlgorithm::Md5,
+			username,
+			realm,
+			password.as_ref(),
+		));
+		let ha1 = unhex(&ha1).expect("MD5 hex digest is 32 hex digits");
+		self.0
+			.insert((username.to_string().into(), realm.to_string().into()), ha1);
+		Ok(())
+	}
+
+	/// Removes the user from the realm, returning whether they were in it
+	pub fn remove_user(&mut self, username: &str, realm: &str) -> bool {
+		let key = (
+			Cow::Owned(username.to_string()),
+			Cow::Owned(realm.to_string()),
+		);
+		self.0.remove(&key).is_some()
+	}
+
+	/// Writes the entries in the same format as `to_string()`
+	pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+		write!(writer, "{}", self)
+	}
+
+	pub fn into_owned(self) -> Htdigest<'static> {
+		Htdigest(
+			self.0
+				.into_iter()
+				.map(|((username, realm), ha1)| {
+					(
+						(username.into_owned().into(), realm.into_owned().into()),
+						ha1,
+					)
+				})
+				.collect(),
+		)
+	}
+}
+
+/// Formats the entries as an htdigest file, one `user:realm:ha1` line per entry sorted by
+/// username and realm
+impl fmt::Display for Htdigest<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut entries = self.0.iter().collect::<Vec<_>>();
+		entries.sort_by(|a, b| a.0.cmp(b.0));
+		for ((username, realm), ha1) in entries {
+			writeln!(f, "{}:{}:{}", username, realm, hex(ha1))?;
+		}
+		Ok(())
+	}
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Option<[u8; DIGEST_SIZE]> {
+	if hex.len() != DIGEST_SIZE * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return None;
+	}
+	let mut bytes = [0u8; DIGEST_SIZE];
+	for (idx, byte) in bytes.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).ok()?;
+	}
+	Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	static DATA: &str = "# staff
+user:Staff:a671186ab0d57af5f1b7601d91d3ef8a
+Mufasa:http-auth@example.org:3D78807DEFE7DE2157E2B0B6573A855F
+";
+
+	/// Example of RFC 7616 section 3.9.1, password "Circle of Life"
+	fn rfc_header(algorithm: &str, response: &str) -> String {
+		format!(
+			r#"Digest username="Mufasa", realm="http-auth@example.org", uri="/dir/index.html", algorithm={}, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", nc=00000001, cnonce="f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ", qop=auth, response="{}", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+			algorithm, response
+		)
+	}
+
+	#[test]
+	fn rfc_7616_responses() {
+		for &(algorithm, expected) in [
+			("MD5", "8ca523f5e9506fed4657c9700eebdbec"),
+			(
+				"SHA-256",
+				"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+			),
+		]
+		.iter()
+		{
+			let credentials = DigestCredentials::parse(&rfc_header(algorithm, expected)).unwrap();
+			assert_eq!(credentials.algorithm.name(), algorithm);
+			assert_eq!(credentials.nonce_count(), Ok(1));
+			let ha1 = digest_ha1(
+				credentials.algorithm,
+				"Mufasa",
+				"http-auth@example.org",
+				"Circle of Life",
+			);
+			assert_eq!(
+				credentials.expected_response(&ha1, "GET").unwrap(),
+				expected
+			);
+		}
+	}
+
+	#[test]
+	fn parse_and_write() {
+		let mut htdigest = Htdigest::parse(DATA).unwrap();
+		assert!(htdigest.check("Mufasa", "http-auth@example.org", "Circle of Life"));
+		assert_eq!(
+			htdigest.verify("user", "Other", "password"),
+			Err(VerifyError::UnknownUser)
+		);
+		htdigest.set_password("user", "Other", "secret").unwrap();
+		assert!(htdigest.remove_user("Mufasa", "http-auth@example.org"));
+		assert!(!htdigest.remove_user("Mufasa", "http-auth@example.org"));
+		assert_eq!(
+			htdigest.set_password("user", "a:b", "password"),
+			Err(UpdateError::InvalidRealm)
+		);
+		assert_eq!(
+			htdigest.set_password("a\nb", "Staff", "password"),
+			Err(UpdateError::InvalidUsername)
+		);
+
+		let mut written = Vec::new();
+		htdigest.write_to(&mut written).unwrap();
+		let written = String::from_utf8(written).unwrap();
+		assert!(written.starts_with("user:Other:"));
+		assert!(written.ends_with("\nuser:Staff:a671186ab0d57af5f1b7601d91d3ef8a\n"));
+		let reloaded = Htdigest::parse(&written).unwrap().into_owned();
+		assert!(reloaded.check("user", "Other", "secret"));
+
+		for &(data, column, kind) in [
+			("user", 5, ParseErrorKind::MissingSeparator),
+			(
+				":Staff:a671186ab0d57af5f1b7601d91d3ef8a",
+				1,
+				ParseErrorKind::EmptyUsername,
+			),
+			("user:Staff", 11, ParseErrorKind::MissingRealmSeparator),
+			(
+				"user:Staff:+671186ab0d57af5f1b7601d91d3ef8a",
+				12,
+				ParseErrorKind::InvalidHtdigestHa1,
+			),
+			(
+				"user:Staff:a671186a",
+				12,
+				ParseErrorKind::InvalidHtdigestHa1,
+			),
+		]
+		.iter()
+		{
+			let err = Htdigest::parse(data).unwrap_err();
+			assert_eq!(
+				(err.line, err.column, err.kind),
+				(1, column, kind),
+				"{}",
+				data
+			);
+		}
+		let err = Htdigest::parse(
+			"u:r:a671186ab0d57af5f1b7601d91d3ef8a\nu:r:a671186ab0d57af5f1b7601d91d3ef8a",
+		)
+		.unwrap_err();
+		assert_eq!((err.line, err.kind), (2, ParseErrorKind::DuplicateUsername));
+	}
+
+	#[test]
+	fn verify_digest_header_counts_nonces() {
+		let htdigest = Htdigest::parse(DATA).unwrap();
+		let nonces = DigestNonces::new(Duration::from_secs(300), 2);
+		let nonce = nonces.issue();
+		let header = |nc: &str, password: &str, uri: &str| {
+			let mut credentials = DigestCredentials {
+				username: "user".to_string(),
+				realm: "Staff".to_string(),
+				nonce: nonce.clone(),
+				uri: uri.to_string(),
+				response: String::new(),
+				algorithm: DigestAlgorithm::Md5,
+				qop: Some("auth".to_string()),
+				nc: Some(nc.to_string()),
+				cnonce: Some("0a4f113b".to_string()),
+				opaque: None,
+			};
+			let ha1 = digest_ha1(DigestAlgorithm::Md5, "user", "Staff", password);
+			credentials.response = credentials.expected_response(&ha1, "GET").unwrap();
+			format!(
+				r#"digest username="user", realm="Staff", uri="{}", nonce="{}", nc={}, cnonce="0a4f113b", qop=auth, response="{}""#,
+				uri, nonce, nc, credentials.response
+			)
+		};
+		let verify =
+			|header: &str| htdigest.verify_digest_header(header, "Staff", "GET", "/", &nonces);
+
+		assert_eq!(
+			verify(&header("00000001", "passwort", "/")),
+			Err(DigestError::InvalidCredentials)
+		);
+		assert_eq!(
+			verify(&header("00000001", "password", "/")).map(|user| user.username),
+			Ok("user".to_string())
+		);
+		assert_eq!(
+			verify(&header("00000001", "password", "/")),
+			Err(DigestError::ReplayedNonce)
+		);
+		assert!(verify(&header("00000003", "password", "/")).is_ok());
+		assert_eq!(
+			verify(&header("00000002", "password", "/")),
+			Err(DigestError::ReplayedNonce)
+		);
+		assert_eq!(
+			verify(&header("00000004", "password", "/other")),
+			Err(DigestError::UriMismatch)
+		);
+		assert_eq!(
+			htdigest.verify_digest_header(
+				&header("00000004", "password", "/"),
+				"Other",
+				"GET",
+				"/",
+				&nonces
+			),
+			Err(DigestError::WrongRealm)
+		);
+		assert_eq!(
+			verify(&rfc_header("SHA-256", "753927fa")),
+			Err(DigestError::Unsupported)
+		);
+		assert_eq!(
+			verify("Basic dXNlcjpwYXNzd29yZA=="),
+			Err(DigestError::UnsupportedScheme)
+		);
+		assert_eq!(
+			verify(r#"Digest username="user", realm="Staff""#),
+			Err(DigestError::Malformed)
+		);
+		assert_eq!(
+			verify(r#"Digest username="user" realm="Staff""#),
+			Err(DigestError::Malformed)
+		);
+
+		// Issuing more nonces than fit forgets the oldest one
+		nonces.issue();
+		nonces.issue();
+		assert_eq!(
+			verify(&header("00000005", "password", "/")),
+			Err(DigestError::StaleNonce)
+		);
+	}
+
+	#[test]
+	fn parses_params() {
+		let params = parse_params(r#" A=b,  c = "d \"e\", f" ,, g=h "#).unwrap();
+		assert_eq!(params["a"], "b");
+		assert_eq!(params["c"], "d \"e\", f");
+		assert_eq!(params["g"], "h");
+		assert_eq!(parse_params(r#"a="b"#), None);
+		assert_eq!(parse_params("a=b, a=c"), None);
+		assert_eq!(parse_params("a"), None);
+	}
+}
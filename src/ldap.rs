@@ -0,0 +1,255 @@
+//! LDAP style `{SCHEME}` hashes, as written by slapd and accepted by nginx
+//!
+//! Digests are stored base64 encoded, salted schemes append the salt after the digest.
+
+use crate::{md5, ParseErrorKind, VerifyError};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use crypto::{
+	digest::Digest,
+	sha1::Sha1,
+	sha2::{Sha256, Sha512},
+};
+use std::borrow::Cow;
+use std::fmt;
+use zeroize::Zeroizing;
+
+pub(crate) static LDAP_CRYPT_ID: &str = "{CRYPT}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LdapScheme {
+	/// `{SSHA}`, salted SHA-1
+	Ssha,
+	/// `{SHA256}`
+	Sha256,
+	/// `{SSHA256}`, salted SHA-256
+	Ssha256,
+	/// `{SHA512}`
+	Sha512,
+	/// `{SSHA512}`, salted SHA-512
+	Ssha512,
+	/// `{MD5}`
+	Md5,
+	/// `{SMD5}`, salted MD5
+	Smd5,
+}
+
+/// Base64 encoded digest of an LDAP style hash, followed by the salt for salted schemes
+#[derive(Debug, Clone)]
+pub struct LdapHash<'a> {
+	pub scheme: LdapScheme,
+	pub digest: Cow<'a, str>,
+}
+
+impl LdapScheme {
+	const ALL: [LdapScheme; 7] = [
+		LdapScheme::Ssha,
+		LdapScheme::Sha256,
+		LdapScheme::Ssha256,
+		LdapScheme::Sha512,
+		LdapScheme::Ssha512,
+		LdapScheme::Md5,
+		LdapScheme::Smd5,
+	];
+
+	/// Hash prefix of this scheme, like `{SSHA}`
+	pub fn prefix(self) -> &'static str {
+		match self {
+			LdapScheme::Ssha => "{SSHA}",
+			LdapScheme::Sha256 => "{SHA256}",
+			LdapScheme::Ssha256 => "{SSHA256}",
+			LdapScheme::Sha512 => "{SHA512}",
+			LdapScheme::Ssha512 => "{SSHA512}",
+			LdapScheme::Md5 => "{MD5}",
+			LdapScheme::Smd5 => "{SMD5}",
+		}
+	}
+
+	pub fn is_salted(self) -> bool {
+		match self {
+			LdapScheme::Ssha | LdapScheme::Ssha256 | LdapScheme::Ssha512 | LdapScheme::Smd5 => true,
+			LdapScheme::Sha256 | LdapScheme::Sha512 | LdapScheme::Md5 => false,
+		}
+	}
+
+	fn digest_len(self) -> usize {
+		match self.hasher() {
+			Some(hasher) => hasher.output_bytes(),
+			None => md5::DIGEST_SIZE,
+		}
+	}
+
+	/// Digest of the password followed by the salt
+	///
+	/// Like for `{SHA}` entries, the SHA hashers' input buffer can't be wiped, only reset. The
+	/// MD5 hasher wipes itself when dropped.
+	fn digest(self, password: &str, salt: &[u8]) -> Zeroizing<Vec<u8>> {
+		let mut hasher = match self.hasher() {
+			Some(hasher) => hasher,
+			None => {
+				let mut hasher = md5::Md5::new();
+				hasher.update(password);
+				hasher.update(salt);
+				return Zeroizing::new(Zeroizing::new(hasher.finalize()).to_vec());
+			}
+		};
+		hasher.input(password.as_bytes());
+		hasher.input(salt);
+		let mut digest = Zeroizing::new(vec![0u8; hasher.output_bytes()]);
+		hasher.result(&mut digest);
+		hasher.reset();
+		digest
+	}
+
+	/// rust-crypto hasher of the SHA schemes, `None` for the MD5 ones which use
+	/// [`md5::Md5`](../md5/struct.Md5.html)
+	fn hasher(self) -> Option<Box<dyn Digest>> {
+		match self {
+			LdapScheme::Ssha => Some(Box::new(Sha1::new())),
+			LdapScheme::Sha256 | LdapScheme::Ssha256 => Some(Box::new(Sha256::new())),
+			LdapScheme::Sha512 | LdapScheme::Ssha512 => Some(Box::new(Sha512::new())),
+			LdapScheme::Md5 | LdapScheme::Smd5 => None,
+		}
+	}
+}
+
+impl LdapHash<'_> {
+	pub fn into_owned(self) -> LdapHash<'static> {
+		LdapHash {
+			scheme: self.scheme,
+			digest: crate::owned(self.digest),
+		}
+	}
+
+	/// Splits the decoded value into the digest and the salt
+	fn decode(&self) -> Result<(Vec<u8>, Vec<u8>), ParseErrorKind> {
+		let mut digest = BASE64
+			.decode(self.digest.as_bytes())
+			.map_err(|_| ParseErrorKind::InvalidLdapBase64)?;
+		let digest_len = self.scheme.digest_len();
+		let valid_len = if self.scheme.is_salted() {
+			digest.len() > digest_len
+		} else {
+			digest.len() == digest_len
+		};
+		if !valid_len {
+			return Err(ParseErrorKind::InvalidLdapDigestLength);
+		}
+		let salt = digest.split_off(digest_len);
+		Ok((digest, salt))
+	}
+
+	pub(crate) fn verify(&self, password: &str) -> Result<bool, VerifyError> {
+		let (expected, salt) = self.decode().map_err(|_| VerifyError::MalformedHash)?;
+		Ok(crate::constant_time_eq(
+			&self.scheme.digest(password, &salt),
+			&expected,
+		))
+	}
+}
+
+/// Hashes the password, `salt` is ignored for unsalted schemes
+pub(crate) fn generate(scheme: LdapScheme, password: &str, salt: &[u8]) -> String {
+	let salt = if scheme.is_salted() { salt } else { &[] };
+	let mut digest = scheme.digest(password, salt);
+	digest.extend_from_slice(salt);
+	format!("{}{}", scheme.prefix(), BASE64.encode(&digest))
+}
+
+impl fmt::Display for LdapHash<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}{}", self.scheme.prefix(), self.digest)
+	}
+}
+
+/// Parses a hash starting with one of the [`LdapScheme`](enum.LdapScheme.html) prefixes
+pub(crate) fn parse(hash: &str) -> Option<Result<LdapHash<'_>, ParseErrorKind>> {
+	let scheme = LdapScheme::ALL
+		.iter()
+		.copied()
+		.find(|scheme| hash.starts_with(scheme.prefix()))?;
+	let hash = LdapHash {
+		scheme,
+		digest: hash[scheme.prefix().len()..].into(),
+	};
+	Some(hash.decode().map(|_| hash))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	static DATA: &str = "ssha:{SSHA}yrht1iYXEIkejLVu42JWkadd80RzYWx0c2FsdA==
+sha256:{SHA256}XohImNooBHFR0OVvjcYpJ3NgPQ1qq73WKhHvch0VQtg=
+ssha256:{SSHA256}DIzeh0gCRMTRu9dAH3C3rr7fWkRT0Bp2ZdtRqvTX3XJzYWx0c2FsdA==
+sha512:{SHA512}sQnzu7wkTrgkQZF+0G1hi5AI3Qmzvv0bXgc5THBqi7mAsdd4Xll27ASbRt9fEyavWi6m0QP9B8lThf+rDKy8hg==
+ssha512:{SSHA512}9ZxHVj4YomwqqFiYKcIjExMLx2ZblYfXRGc4KMqbgvHq2+HOgwiTIi+eO/Uam/8D0beDAkGpvx14+UFlfBskLnNhbHRzYWx0
+md5:{MD5}X03MO1qnZdYdgyfeuILPmQ==
+smd5:{SMD5}/b3zQZ//mL2wJBOQ9iqds3NhbHRzYWx0
+crypt:{CRYPT}bGVh02xkuGli2
+crypt_md5:{CRYPT}$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/
+crypt_sha512:{CRYPT}$6$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/";
+
+	#[test]
+	fn ldap_verify() {
+		let htpasswd = crate::try_load(DATA).unwrap();
+		for line in DATA.lines() {
+			let username = &line[..line.find(':').unwrap()];
+			assert!(htpasswd.check(username, "password"), "{}", username);
+			assert!(!htpasswd.check(username, "passwort"), "{}", username);
+		}
+	}
+
+	#[test]
+	fn ldap_salt_split() {
+		let hash = parse("{SSHA}yrht1iYXEIkejLVu42JWkadd80RzYWx0c2FsdA==")
+			.unwrap()
+			.unwrap();
+		let (digest, salt) = hash.decode().unwrap();
+		assert_eq!(digest.len(), 20);
+		assert_eq!(salt, b"saltsalt");
+	}
+
+	#[test]
+	fn ldap_parse_errors() {
+		assert_eq!(
+			parse("{SSHA}yrht1iYXEIkejLVu42JWkadd80R!")
+				.unwrap()
+				.unwrap_err(),
+			ParseErrorKind::InvalidLdapBase64
+		);
+		assert_eq!(
+			parse("{SHA256}XohImNooBHFR0OVvjcYpJ3NgPQ1qq73WKhHvch0VQtgA")
+				.unwrap()
+				.unwrap_err(),
+			ParseErrorKind::InvalidLdapDigestLength
+		);
+		assert_eq!(
+			parse("{SMD5}X03MO1qnZdYdgyfeuILPmQ==")
+				.unwrap()
+				.unwrap_err(),
+			ParseErrorKind::InvalidLdapDigestLength
+		);
+		assert!(parse("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=").is_none());
+		assert!(matches!(
+			crate::Hash::parse("{CRYPT}password"),
+			crate::Hash::Unknown(_)
+		));
+		assert!(matches!(
+			crate::Hash::parse("{CRYPT}{CRYPT}bGVh02xkuGli2"),
+			crate::Hash::Unknown(_)
+		));
+		let nested = format!("{}bGVh02xkuGli2", LDAP_CRYPT_ID.repeat(200_000));
+		assert!(matches!(
+			crate::Hash::parse(&nested),
+			crate::Hash::Unknown(_)
+		));
+	}
+
+	#[test]
+	fn ldap_display_round_trip() {
+		for line in DATA.lines() {
+			let hash = &line[(line.find(':').unwrap() + 1)..];
+			assert_eq!(crate::Hash::parse(hash).to_string(), hash);
+		}
+	}
+}
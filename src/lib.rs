@@ -1,6 +1,7 @@
 //! Verify apache's htpasswd file
 //!
-//! Supports MD5, BCrypt, SHA1, Unix crypt
+//! Supports MD5, MD5-crypt, BCrypt, SHA1, SHA-256/SHA-512 crypt, Unix crypt, Argon2, scrypt and LDAP style
+//! `{SSHA}` like hashes
 //!
 //! # Examples
 //!
@@ -24,54 +25,423 @@
 //! assert_eq!(hash, "$apr1$RandSalt$PgCXHRrkpSt4cbyC2C6bm/");
 //! ```
 
-use crate::md5::APR1_ID;
+#![forbid(unsafe_code)]
+
+use crate::generate::DummySpec;
+use crate::md5::{APR1_ID, MD5_CRYPT_ID};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use crypto::{digest::Digest, sha1::Sha1};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, PoisonError, RwLock};
+use zeroize::Zeroizing;
+
+pub use basic::{
+	basic_auth_challenge, AuthenticatedUser, MAX_BASIC_AUTH_HEADER_LEN, MAX_BASIC_AUTH_USERNAME_LEN,
+};
+pub use cache::{CacheOptions, CachingVerifier};
+pub use diagnostic::{Diagnostic, DiagnosticKind};
+pub use document::HtpasswdDocument;
+pub use error::{
+	AuthError, DigestError, GenerateError, ParseError, ParseErrorKind, PoolError, ReloadError,
+	UpdateError, VerifyError,
+};
+#[cfg(feature = "file")]
+pub use file::HtpasswdFile;
+pub use generate::{Params, Scheme};
+pub use htdigest::{digest_ha1, DigestAlgorithm, DigestCredentials, DigestNonces, Htdigest};
+pub use ldap::{LdapHash, LdapScheme};
+#[cfg(feature = "tower")]
+pub use middleware::{BasicAuth, BasicAuthFuture, BasicAuthLayer};
+#[cfg(all(feature = "tower", feature = "tokio"))]
+pub use middleware::{PooledBasicAuth, PooledBasicAuthLayer};
+#[cfg(feature = "tokio")]
+pub use pool::VerifyPool;
+pub use secret::SecretPassword;
+pub use watch::{HtpasswdWatcher, ReloadEvent, WatchOptions};
 
+mod basic;
+mod cache;
+mod diagnostic;
+mod document;
+mod edit;
+mod error;
+#[cfg(feature = "file")]
+mod file;
+mod generate;
+mod htdigest;
+mod ldap;
 pub mod md5;
+#[cfg(feature = "tower")]
+mod middleware;
+pub mod phc;
+#[cfg(feature = "tokio")]
+mod pool;
+mod secret;
+mod watch;
 
-static BCRYPT_ID: &str = "$2y$";
 static SHA1_ID: &str = "{SHA}";
 
-pub struct Htpasswd<'a>(pub HashMap<&'a str, Hash<'a>>);
+/// Most entries looked at to find the dominant scheme and cost of a file
+const DUMMY_SAMPLE_LEN: usize = 32;
 
-#[derive(Debug)]
+/// Scheme, cost and PHC parameters, see `Hash::timing_class`
+type TimingClass = (&'static str, u32, String);
+
+/// Most timing classes whose dummy hash is kept, the oldest one is dropped to make room
+const MAX_DUMMY_HASHES: usize = 16;
+
+/// Dummy hashes of the schemes and costs seen last, shared by all files
+static DUMMY_HASHES: DummyHashes = DummyHashes(RwLock::new(VecDeque::new()));
+static SHA256_CRYPT_ID: &str = "$5$";
+static SHA512_CRYPT_ID: &str = "$6$";
+static SHA_CRYPT_ROUNDS: &str = "rounds=";
+
+/// Most rounds a SHA-crypt hash is verified with.
+///
+/// glibc allows up to 999,999,999 rounds, which takes minutes to verify. Such hashes parse, but
+/// verifying them fails with [`VerifyError::CostTooHigh`](enum.VerifyError.html#variant.CostTooHigh).
+pub const MAX_SHA_CRYPT_ROUNDS: u32 = 1_000_000;
+
+/// Parsed htpasswd file
+///
+/// Borrows from the file contents, use [`into_owned`](#method.into_owned) to get a
+/// `Htpasswd<'static>` that can be moved between threads or stored in a `static`.
+#[derive(Debug, Clone)]
+pub struct Htpasswd<'a>(pub HashMap<Cow<'a, str>, Hash<'a>>);
+
+#[derive(Debug, Clone)]
 pub enum Hash<'a> {
 	MD5(MD5Hash<'a>),
-	BCrypt(&'a str),
-	SHA1(&'a str),
-	Crypt(&'a str),
+	/// Standard `$1$` MD5-crypt, the same algorithm as apache's `$apr1$` with another prefix
+	Md5Crypt(MD5Hash<'a>),
+	BCrypt(BCryptHash<'a>),
+	SHA1(Cow<'a, str>),
+	Crypt(Cow<'a, str>),
+	Sha256Crypt(ShaCryptHash<'a>),
+	Sha512Crypt(ShaCryptHash<'a>),
+	/// `{SSHA}`, `{SHA256}` and the other LDAP style schemes
+	Ldap(LdapHash<'a>),
+	/// `{CRYPT}` followed by a crypt, MD5-crypt, SHA-crypt or bcrypt hash
+	LdapCrypt(Box<Hash<'a>>),
+	/// Argon2 PHC string, `$argon2id$`, `$argon2i$` or `$argon2d$`
+	Argon2(Cow<'a, str>),
+	/// scrypt PHC string, `$scrypt$`
+	Scrypt(Cow<'a, str>),
+	/// Unprefixed value that isn't a crypt hash, only accepted with
+	/// [`PlaintextPolicy::AllowPlaintext`](enum.PlaintextPolicy.html#variant.AllowPlaintext)
+	Plaintext(Cow<'a, str>),
+	/// Unsupported hash, never verifies
+	Unknown(Cow<'a, str>),
+	/// Hash of a supported scheme that failed to parse, never verifies
+	Malformed(Cow<'a, str>, ParseErrorKind),
 }
 
-#[derive(Debug)]
+/// Whether [`Hash::Plaintext`](enum.Hash.html#variant.Plaintext) entries can be verified
+///
+/// Apache only accepts plaintext passwords on Windows and NetWare, everywhere else they never
+/// match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaintextPolicy {
+	#[default]
+	Reject,
+	AllowPlaintext,
+}
+
+#[derive(Debug, Clone)]
 pub struct MD5Hash<'a> {
-	pub salt: &'a str,
-	pub hash: &'a str,
+	pub salt: Cow<'a, str>,
+	pub hash: Cow<'a, str>,
+}
+
+/// SHA-256 or SHA-512 crypt hash, `$5$` or `$6$` followed by optional `rounds=N$`, the salt
+/// and the hash
+#[derive(Debug, Clone)]
+pub struct ShaCryptHash<'a> {
+	/// Explicit number of rounds, 5000 if not given
+	pub rounds: Option<u32>,
+	pub salt: Cow<'a, str>,
+	pub hash: Cow<'a, str>,
+}
+
+impl MD5Hash<'_> {
+	pub fn into_owned(self) -> MD5Hash<'static> {
+		MD5Hash {
+			salt: owned(self.salt),
+			hash: owned(self.hash),
+		}
+	}
+}
+
+impl ShaCryptHash<'_> {
+	pub fn into_owned(self) -> ShaCryptHash<'static> {
+		ShaCryptHash {
+			rounds: self.rounds,
+			salt: owned(self.salt),
+			hash: owned(self.hash),
+		}
+	}
+
+	fn setup(&self) -> pwhash::HashSetup<'_> {
+		pwhash::HashSetup {
+			salt: Some(&self.salt),
+			rounds: self.rounds,
+		}
+	}
+}
+
+/// bcrypt hash, `$2y$05$` followed by the salt and the hash
+///
+/// Formats back to the original string:
+///
+/// ```
+/// use htpasswd_verify::{BCryptHash, BCryptVersion, Hash};
+///
+/// let entry = "$2b$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa";
+/// let hash = Hash::parse(entry);
+/// assert!(matches!(hash, Hash::BCrypt(BCryptHash { version: BCryptVersion::TwoB, cost: 5, .. })));
+/// assert_eq!(hash.to_string(), entry);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BCryptHash<'a> {
+	pub version: BCryptVersion,
+	pub cost: u32,
+	pub salt: Cow<'a, str>,
+	pub hash: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BCryptVersion {
+	TwoA,
+	TwoB,
+	TwoX,
+	TwoY,
+}
+
+impl BCryptVersion {
+	const ALL: [BCryptVersion; 4] = [
+		BCryptVersion::TwoA,
+		BCryptVersion::TwoB,
+		BCryptVersion::TwoX,
+		BCryptVersion::TwoY,
+	];
+
+	/// Hash prefix of this version, like `$2y$`
+	pub fn prefix(self) -> &'static str {
+		match self {
+			BCryptVersion::TwoA => "$2a$",
+			BCryptVersion::TwoB => "$2b$",
+			BCryptVersion::TwoX => "$2x$",
+			BCryptVersion::TwoY => "$2y$",
+		}
+	}
+}
+
+impl BCryptHash<'_> {
+	pub fn into_owned(self) -> BCryptHash<'static> {
+		BCryptHash {
+			version: self.version,
+			cost: self.cost,
+			salt: owned(self.salt),
+			hash: owned(self.hash),
+		}
+	}
+}
+
+impl fmt::Display for BCryptHash<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}{:02}${}{}",
+			self.version.prefix(),
+			self.cost,
+			self.salt,
+			self.hash
+		)
+	}
+}
+
+impl fmt::Display for Hash<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Hash::MD5(hash) => write!(f, "{}{}${}", APR1_ID, hash.salt, hash.hash),
+			Hash::Md5Crypt(hash) => write!(f, "{}{}${}", MD5_CRYPT_ID, hash.salt, hash.hash),
+			Hash::BCrypt(hash) => hash.fmt(f),
+			Hash::SHA1(hash) => write!(f, "{}{}", SHA1_ID, hash),
+			Hash::Sha256Crypt(hash) => fmt_sha_crypt(f, SHA256_CRYPT_ID, hash),
+			Hash::Sha512Crypt(hash) => fmt_sha_crypt(f, SHA512_CRYPT_ID, hash),
+			Hash::Ldap(hash) => hash.fmt(f),
+			Hash::LdapCrypt(hash) => write!(f, "{}{}", ldap::LDAP_CRYPT_ID, hash),
+			Hash::Crypt(hash)
+			| Hash::Argon2(hash)
+			| Hash::Scrypt(hash)
+			| Hash::Plaintext(hash)
+			| Hash::Unknown(hash)
+			| Hash::Malformed(hash, _) => f.write_str(hash),
+		}
+	}
+}
+
+fn fmt_sha_crypt(f: &mut fmt::Formatter<'_>, id: &str, hash: &ShaCryptHash<'_>) -> fmt::Result {
+	f.write_str(id)?;
+	if let Some(rounds) = hash.rounds {
+		write!(f, "{}{}$", SHA_CRYPT_ROUNDS, rounds)?;
+	}
+	write!(f, "{}${}", hash.salt, hash.hash)
+}
+
+impl Hash<'_> {
+	/// Copies borrowed parts of the hash so it no longer depends on the parsed string
+	pub fn into_owned(self) -> Hash<'static> {
+		match self {
+			Hash::MD5(hash) => Hash::MD5(hash.into_owned()),
+			Hash::Md5Crypt(hash) => Hash::Md5Crypt(hash.into_owned()),
+			Hash::BCrypt(hash) => Hash::BCrypt(hash.into_owned()),
+			Hash::SHA1(hash) => Hash::SHA1(owned(hash)),
+			Hash::Crypt(hash) => Hash::Crypt(owned(hash)),
+			Hash::Sha256Crypt(hash) => Hash::Sha256Crypt(hash.into_owned()),
+			Hash::Sha512Crypt(hash) => Hash::Sha512Crypt(hash.into_owned()),
+			Hash::Ldap(hash) => Hash::Ldap(hash.into_owned()),
+			Hash::LdapCrypt(hash) => Hash::LdapCrypt(Box::new(hash.into_owned())),
+			Hash::Argon2(hash) => Hash::Argon2(owned(hash)),
+			Hash::Scrypt(hash) => Hash::Scrypt(owned(hash)),
+			Hash::Plaintext(hash) => Hash::Plaintext(owned(hash)),
+			Hash::Unknown(hash) => Hash::Unknown(owned(hash)),
+			Hash::Malformed(hash, kind) => Hash::Malformed(owned(hash), kind),
+		}
+	}
+}
+
+/// Turns a borrowed string into an owned one with a `'static` lifetime
+pub(crate) fn owned(value: Cow<'_, str>) -> Cow<'static, str> {
+	Cow::Owned(value.into_owned())
 }
 
 impl<'a> Hash<'a> {
-	pub fn check(&self, password: &str) -> bool {
+	/// Verifies the password, rejecting plaintext entries
+	pub fn check(&self, password: impl AsRef<str>) -> bool {
+		self.check_with_policy(password, PlaintextPolicy::Reject)
+	}
+
+	/// Verifies the password, failing closed if the hash can't be used
+	pub fn check_with_policy(&self, password: impl AsRef<str>, plaintext: PlaintextPolicy) -> bool {
+		self.verify_with_policy(password, plaintext)
+			.unwrap_or(false)
+	}
+
+	/// Verifies the password, rejecting plaintext entries
+	///
+	/// ```
+	/// use htpasswd_verify::{Hash, VerifyError};
+	///
+	/// let hash = Hash::parse("$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa");
+	/// assert_eq!(hash.verify("password"), Ok(true));
+	/// assert_eq!(hash.verify("passwort"), Ok(false));
+	/// assert_eq!(Hash::parse("$5$unknown").verify("password"), Err(VerifyError::MalformedHash));
+	/// assert_eq!(Hash::parse("{PBKDF2}c2FsdA==").verify("password"), Err(VerifyError::UnsupportedFormat));
+	/// ```
+	pub fn verify(&self, password: impl AsRef<str>) -> Result<bool, VerifyError> {
+		self.verify_with_policy(password, PlaintextPolicy::Reject)
+	}
+
+	pub fn verify_with_policy(
+		&self,
+		password: impl AsRef<str>,
+		plaintext: PlaintextPolicy,
+	) -> Result<bool, VerifyError> {
+		let password = password.as_ref();
 		match self {
-			Hash::MD5(hash) => md5::md5_apr1_encode(password, hash.salt).as_str() == hash.hash,
-			Hash::BCrypt(hash) => bcrypt::verify(password, hash).unwrap(),
+			Hash::MD5(hash) => Ok(constant_time_eq(
+				Zeroizing::new(md5::md5_apr1_encode(password, &hash.salt)).as_bytes(),
+				hash.hash.as_bytes(),
+			)),
+			Hash::Md5Crypt(hash) => Ok(constant_time_eq(
+				Zeroizing::new(md5::md5_crypt_encode(password, &hash.salt, MD5_CRYPT_ID))
+					.as_bytes(),
+				hash.hash.as_bytes(),
+			)),
+			Hash::BCrypt(hash) => {
+				let parts = hash
+					.to_string()
+					.parse::<bcrypt::HashParts>()
+					.map_err(|_| VerifyError::MalformedHash)?;
+				let computed = Zeroizing::new(
+					bcrypt::hash_with_salt(password, parts.get_cost(), parts.get_salt_raw())
+						.map_err(|_| VerifyError::MalformedHash)?
+						.format_for_version(hash.version.into()),
+				);
+				let digest = &computed[(computed.len() - hash.hash.len())..];
+				Ok(constant_time_eq(digest.as_bytes(), hash.hash.as_bytes()))
+			}
 			Hash::SHA1(hash) => {
-				let mut hasher = Sha1::new();
-				hasher.input_str(password);
-				let size = hasher.output_bytes();
-				let mut buf = vec![0u8; size];
-				hasher.result(&mut buf);
-				base64::encode(&buf).as_str() == *hash
+				let expected = BASE64
+					.decode(hash.as_bytes())
+					.map_err(|_| VerifyError::MalformedHash)?;
+				Ok(constant_time_eq(&sha1_digest(password), &expected))
+			}
+			Hash::Sha256Crypt(hash) => {
+				verify_sha_crypt(hash, |setup| sha256_crypt_hash_with(setup, password))
+			}
+			Hash::Sha512Crypt(hash) => verify_sha_crypt(hash, |setup| {
+				pwhash::sha512_crypt::hash_with(setup, password)
+			}),
+			Hash::Ldap(hash) => hash.verify(password),
+			Hash::LdapCrypt(hash) => hash.verify(password),
+			Hash::Argon2(hash) => phc::verify_argon2(hash, password),
+			Hash::Scrypt(hash) => phc::verify_scrypt(hash, password),
+			Hash::Crypt(hash) => {
+				if hash.len() != CRYPT_HASH_LEN || !hash.bytes().all(is_crypt_char) {
+					return Err(VerifyError::MalformedHash);
+				}
+				#[allow(deprecated)]
+				let computed = Zeroizing::new(
+					pwhash::unix_crypt::hash_with(&hash[..2], password)
+						.map_err(|_| VerifyError::MalformedHash)?,
+				);
+				Ok(constant_time_eq(computed.as_bytes(), hash.as_bytes()))
+			}
+			Hash::Plaintext(hash) => match plaintext {
+				PlaintextPolicy::AllowPlaintext => {
+					Ok(constant_time_eq(password.as_bytes(), hash.as_bytes()))
+				}
+				PlaintextPolicy::Reject => Err(VerifyError::PlaintextRejected),
+			},
+			Hash::Unknown(_) => Err(VerifyError::UnsupportedFormat),
+			Hash::Malformed(..) => Err(VerifyError::MalformedHash),
+		}
+	}
+
+	/// Scheme and cost parameters, hashes with the same class take about as long to verify
+	fn timing_class(&self) -> (&'static str, u32, &str) {
+		match self {
+			Hash::MD5(_) | Hash::Md5Crypt(_) => ("md5-crypt", 0, ""),
+			Hash::BCrypt(hash) => ("bcrypt", hash.cost, ""),
+			Hash::SHA1(_) => ("sha1", 0, ""),
+			Hash::Crypt(_) => ("crypt", 0, ""),
+			Hash::Sha256Crypt(hash) => ("sha256-crypt", hash.rounds.unwrap_or(5000), ""),
+			Hash::Sha512Crypt(hash) => ("sha512-crypt", hash.rounds.unwrap_or(5000), ""),
+			Hash::Ldap(hash) => (hash.scheme.prefix(), 0, ""),
+			Hash::LdapCrypt(hash) => hash.timing_class(),
+			// Algorithm, version and parameters, without the salt and the digest
+			Hash::Argon2(hash) | Hash::Scrypt(hash) => {
+				("phc", 0, hash.rsplitn(3, '$').nth(2).unwrap_or_default())
 			}
-			Hash::Crypt(hash) => pwhash::unix_crypt::verify(password, hash),
+			Hash::Plaintext(_) => ("plaintext", 0, ""),
+			Hash::Unknown(_) => ("unknown", 0, ""),
+			Hash::Malformed(..) => ("malformed", 0, ""),
 		}
 	}
 
 	/// Parses the hash part of the htpasswd entry.
 	///
+	/// Malformed hashes become [`Hash::Malformed`](enum.Hash.html#variant.Malformed) with the
+	/// reason they failed to parse, and won't verify against any password.
+	///
 	/// Example:
 	///
 	/// ```
-	/// use htpasswd_verify::{Hash, MD5Hash};
+	/// use htpasswd_verify::Hash;
 	///
 	/// let entry = "user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00";
 	/// let semicolon = entry.find(':').unwrap();
@@ -80,58 +450,697 @@ impl<'a> Hash<'a> {
 	/// let hash_id = &entry[(semicolon + 1)..];
 	/// assert_eq!(hash_id, "$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00");
 	/// let hash = Hash::parse(hash_id);
-	/// assert!(matches!(hash, Hash::MD5(MD5Hash { salt: "lZL6V/ci", hash: "eIMz/iKDkbtys/uU7LEK00"})));
+	/// let md5 = match hash { Hash::MD5(md5) => md5, _ => unreachable!() };
+	/// assert_eq!((md5.salt.as_ref(), md5.hash.as_ref()), ("lZL6V/ci", "eIMz/iKDkbtys/uU7LEK00"));
 	/// ```
 	pub fn parse(hash: &'a str) -> Self {
-		if hash.starts_with(md5::APR1_ID) {
-			Hash::MD5(MD5Hash {
-				salt: &hash[(APR1_ID.len())..(APR1_ID.len() + 8)],
-				hash: &hash[(APR1_ID.len() + 8 + 1)..],
-			})
-		} else if hash.starts_with(BCRYPT_ID) {
-			Hash::BCrypt(&hash)
-		} else if hash.starts_with("{SHA}") {
-			Hash::SHA1(&hash[SHA1_ID.len()..])
-		} else {
-			//Ignore plaintext, assume crypt
-			Hash::Crypt(&hash)
+		Self::try_parse(hash).unwrap_or_else(|kind| Hash::Malformed(hash.into(), kind))
+	}
+
+	/// Parses the hash part of the htpasswd entry, validating its format.
+	///
+	/// ```
+	/// use htpasswd_verify::{Hash, ParseErrorKind};
+	///
+	/// let hash = Hash::try_parse("$apr1$lZL6");
+	/// assert_eq!(hash.unwrap_err(), ParseErrorKind::TruncatedApr1Salt);
+	/// ```
+	pub fn try_parse(hash: &'a str) -> Result<Self, ParseErrorKind> {
+		parse_hash(hash).map_err(|(_, kind)| kind)
+	}
+}
+
+const APR1_SALT_MAX_LEN: usize = 8;
+const APR1_HASH_LEN: usize = 22;
+const BCRYPT_HASH_LEN: usize = 53;
+const BCRYPT_SALT_LEN: usize = 22;
+const CRYPT_HASH_LEN: usize = 13;
+const SHA_CRYPT_SALT_MAX_LEN: usize = 16;
+const SHA256_CRYPT_HASH_LEN: usize = 43;
+const SHA512_CRYPT_HASH_LEN: usize = 86;
+const SHA1_DIGEST_LEN: usize = 20;
+
+/// Compares in a time that only depends on the lengths, so that a wrong password doesn't reveal
+/// how many leading bytes of the digest it got right. Every verifier goes through this.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	#[cfg(test)]
+	tests::CONSTANT_TIME_EQ_CALLS.with(|calls| calls.set(calls.get() + 1));
+	if a.len() != b.len() {
+		return false;
+	}
+	let diff = a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y));
+	std::hint::black_box(diff) == 0
+}
+
+/// SHA-1 digest of the password
+///
+/// rust-crypto's hashers don't implement `Zeroize`, so `reset` is the best we can do: it clears
+/// the state words, but the partially filled input block inside the hasher is left as is.
+fn sha1_digest(password: &str) -> Zeroizing<Vec<u8>> {
+	let mut hasher = Sha1::new();
+	hasher.input_str(password);
+	let size = hasher.output_bytes();
+	let mut buf = Zeroizing::new(vec![0u8; size]);
+	hasher.result(&mut buf);
+	hasher.reset();
+	buf
+}
+
+/// Parses the hash, returning the byte offset of the problem on failure
+fn parse_hash(hash: &str) -> Result<Hash<'_>, (usize, ParseErrorKind)> {
+	if hash.starts_with(APR1_ID) {
+		parse_md5(hash, APR1_ID).map(Hash::MD5)
+	} else if hash.starts_with(MD5_CRYPT_ID) {
+		parse_md5(hash, MD5_CRYPT_ID).map(Hash::Md5Crypt)
+	} else if let Some(version) = BCryptVersion::ALL
+		.iter()
+		.copied()
+		.find(|version| hash.starts_with(version.prefix()))
+	{
+		let id_len = version.prefix().len();
+		let rest = &hash[id_len..];
+		let cost = rest
+			.get(..2)
+			.filter(|cost| cost.bytes().all(|b| b.is_ascii_digit()))
+			.and_then(|cost| cost.parse().ok())
+			.filter(|cost| (4..=31).contains(cost))
+			.filter(|_| rest[2..].starts_with('$'))
+			.ok_or((id_len, ParseErrorKind::MalformedBcryptCost))?;
+		let digest = &rest[3..];
+		if digest.len() != BCRYPT_HASH_LEN || !digest.bytes().all(is_crypt_char) {
+			return Err((id_len + 3, ParseErrorKind::MalformedBcryptHash));
+		}
+		Ok(Hash::BCrypt(BCryptHash {
+			version,
+			cost,
+			salt: digest[..BCRYPT_SALT_LEN].into(),
+			hash: digest[BCRYPT_SALT_LEN..].into(),
+		}))
+	} else if let Some(digest) = hash.strip_prefix(SHA1_ID) {
+		let decoded = BASE64
+			.decode(digest)
+			.map_err(|_| (SHA1_ID.len(), ParseErrorKind::InvalidSha1Base64))?;
+		if decoded.len() != SHA1_DIGEST_LEN {
+			return Err((SHA1_ID.len(), ParseErrorKind::InvalidSha1Length));
+		}
+		Ok(Hash::SHA1(digest.into()))
+	} else if let Some(ldap) = ldap::parse(hash) {
+		ldap.map(Hash::Ldap)
+			.map_err(|kind| (hash.find('}').unwrap_or_default() + 1, kind))
+	} else if let Some(inner) = hash.strip_prefix(ldap::LDAP_CRYPT_ID) {
+		// Only one level, so nested prefixes can't recurse without bound
+		if inner.starts_with(ldap::LDAP_CRYPT_ID) {
+			return Ok(Hash::Unknown(hash.into()));
+		}
+		let offset = ldap::LDAP_CRYPT_ID.len();
+		match parse_hash(inner).map_err(|(idx, kind)| (offset + idx, kind))? {
+			inner @ Hash::Crypt(_)
+			| inner @ Hash::Md5Crypt(_)
+			| inner @ Hash::Sha256Crypt(_)
+			| inner @ Hash::Sha512Crypt(_)
+			| inner @ Hash::BCrypt(_) => Ok(Hash::LdapCrypt(Box::new(inner))),
+			_ => Ok(Hash::Unknown(hash.into())),
 		}
+	} else if phc::ARGON2_IDS.iter().any(|id| hash.starts_with(id)) {
+		phc::parse_argon2(hash)
+			.map(|_| Hash::Argon2(hash.into()))
+			.map_err(|kind| (0, kind))
+	} else if hash.starts_with(phc::SCRYPT_ID) {
+		phc::parse_scrypt(hash)
+			.map(|_| Hash::Scrypt(hash.into()))
+			.map_err(|kind| (0, kind))
+	} else if hash.starts_with(SHA256_CRYPT_ID) {
+		parse_sha_crypt(hash, SHA256_CRYPT_ID, SHA256_CRYPT_HASH_LEN).map(Hash::Sha256Crypt)
+	} else if hash.starts_with(SHA512_CRYPT_ID) {
+		parse_sha_crypt(hash, SHA512_CRYPT_ID, SHA512_CRYPT_HASH_LEN).map(Hash::Sha512Crypt)
+	} else if hash.len() == CRYPT_HASH_LEN && hash.bytes().all(is_crypt_char) {
+		Ok(Hash::Crypt(hash.into()))
+	} else if has_scheme_prefix(hash) {
+		Ok(Hash::Unknown(hash.into()))
+	} else {
+		Ok(Hash::Plaintext(hash.into()))
 	}
 }
 
+fn parse_md5<'a>(hash: &'a str, id: &str) -> Result<MD5Hash<'a>, (usize, ParseErrorKind)> {
+	let rest = &hash[id.len()..];
+	let salt_len = rest
+		.find('$')
+		.filter(|&len| len <= APR1_SALT_MAX_LEN)
+		.ok_or((id.len(), ParseErrorKind::TruncatedApr1Salt))?;
+	let digest_start = id.len() + salt_len + 1;
+	let digest = &hash[digest_start..];
+	if digest.len() != APR1_HASH_LEN || !digest.bytes().all(is_crypt_char) {
+		return Err((digest_start, ParseErrorKind::InvalidApr1Hash));
+	}
+	Ok(MD5Hash {
+		salt: rest[..salt_len].into(),
+		hash: digest.into(),
+	})
+}
+
+fn parse_sha_crypt<'a>(
+	hash: &'a str,
+	id: &str,
+	hash_len: usize,
+) -> Result<ShaCryptHash<'a>, (usize, ParseErrorKind)> {
+	let mut offset = id.len();
+	let rounds = match hash[offset..].strip_prefix(SHA_CRYPT_ROUNDS) {
+		Some(rest) => {
+			let rounds_len = rest
+				.find('$')
+				.ok_or((offset, ParseErrorKind::MalformedShaCryptRounds))?;
+			let rounds = Some(&rest[..rounds_len])
+				.filter(|rounds| rounds.bytes().all(|b| b.is_ascii_digit()))
+				.and_then(|rounds| rounds.parse().ok())
+				.filter(|rounds| {
+					(pwhash::sha256_crypt::MIN_ROUNDS..=pwhash::sha256_crypt::MAX_ROUNDS)
+						.contains(rounds)
+				})
+				.ok_or((offset, ParseErrorKind::MalformedShaCryptRounds))?;
+			offset += SHA_CRYPT_ROUNDS.len() + rounds_len + 1;
+			Some(rounds)
+		}
+		None => None,
+	};
+	// Salts longer than 16 characters are kept, pwhash truncates them like glibc does
+	let rest = &hash[offset..];
+	let salt_len = rest
+		.find('$')
+		.ok_or((offset, ParseErrorKind::TruncatedShaCryptSalt))?;
+	let digest_start = offset + salt_len + 1;
+	let digest = &hash[digest_start..];
+	if digest.len() != hash_len || !digest.bytes().all(is_crypt_char) {
+		return Err((digest_start, ParseErrorKind::InvalidShaCryptHash));
+	}
+	Ok(ShaCryptHash {
+		rounds,
+		salt: rest[..salt_len].into(),
+		hash: digest.into(),
+	})
+}
+
+/// `pwhash::sha256_crypt::hash_with`, deprecated by pwhash 0.3 as not meant for new passwords,
+/// with the allow limited to this one call
+#[allow(deprecated)]
+pub(crate) fn sha256_crypt_hash_with(
+	setup: pwhash::HashSetup<'_>,
+	password: &str,
+) -> pwhash::Result<String> {
+	pwhash::sha256_crypt::hash_with(setup, password)
+}
+
+/// Compares the digest of a hash computed by pwhash against the stored one, refusing more
+/// rounds than [`MAX_SHA_CRYPT_ROUNDS`](constant.MAX_SHA_CRYPT_ROUNDS.html)
+fn verify_sha_crypt(
+	hash: &ShaCryptHash<'_>,
+	compute: impl FnOnce(pwhash::HashSetup<'_>) -> pwhash::Result<String>,
+) -> Result<bool, VerifyError> {
+	if hash.rounds.unwrap_or_default() > MAX_SHA_CRYPT_ROUNDS {
+		return Err(VerifyError::CostTooHigh);
+	}
+	let computed = Zeroizing::new(compute(hash.setup()).map_err(|_| VerifyError::MalformedHash)?);
+	let digest = &computed[(computed.rfind('$').unwrap_or_default() + 1)..];
+	Ok(constant_time_eq(digest.as_bytes(), hash.hash.as_bytes()))
+}
+
+/// Whether the hash starts like `$id$` or `{SCHEME}`, the prefixes of hash formats we don't know
+fn has_scheme_prefix(hash: &str) -> bool {
+	let (rest, close) = if let Some(rest) = hash.strip_prefix('$') {
+		(rest, '$')
+	} else if let Some(rest) = hash.strip_prefix('{') {
+		(rest, '}')
+	} else {
+		return false;
+	};
+	match rest.find(close) {
+		Some(len) if len > 0 => rest[..len]
+			.bytes()
+			.all(|b| b.is_ascii_alphanumeric() || b == b'-'),
+		_ => false,
+	}
+}
+
+/// Characters of the `./0-9A-Za-z` alphabet used by crypt style hashes
+fn is_crypt_char(b: u8) -> bool {
+	b.is_ascii_alphanumeric() || b == b'.' || b == b'/'
+}
+
 impl Htpasswd<'_> {
-	pub fn check(&self, username: &str, password: &str) -> bool {
+	/// Verifies the user's password, rejecting plaintext entries
+	pub fn check(&self, username: &str, password: impl AsRef<str>) -> bool {
+		self.check_with_policy(username, password, PlaintextPolicy::Reject)
+	}
+
+	/// Verifies the user's password
+	///
+	/// ```
+	/// use htpasswd_verify::PlaintextPolicy;
+	///
+	/// let htpasswd = htpasswd_verify::load("user:password");
+	/// assert!(!htpasswd.check("user", "password"));
+	/// assert!(htpasswd.check_with_policy("user", "password", PlaintextPolicy::AllowPlaintext));
+	/// ```
+	pub fn check_with_policy(
+		&self,
+		username: &str,
+		password: impl AsRef<str>,
+		plaintext: PlaintextPolicy,
+	) -> bool {
+		self.verify_with_policy(username, password, plaintext)
+			.unwrap_or(false)
+	}
+
+	/// Verifies the user's password, rejecting plaintext entries
+	///
+	/// Returns `Ok(false)` for a wrong password, and an error if the user doesn't exist or their
+	/// hash can't be used.
+	///
+	/// ```
+	/// use htpasswd_verify::VerifyError;
+	///
+	/// let htpasswd = htpasswd_verify::load("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00");
+	/// assert_eq!(htpasswd.verify("user", "password"), Ok(true));
+	/// assert_eq!(htpasswd.verify("user", "passwort"), Ok(false));
+	/// assert_eq!(htpasswd.verify("nobody", "password"), Err(VerifyError::UnknownUser));
+	/// ```
+	pub fn verify(&self, username: &str, password: impl AsRef<str>) -> Result<bool, VerifyError> {
+		self.verify_with_policy(username, password, PlaintextPolicy::Reject)
+	}
+
+	pub fn verify_with_policy(
+		&self,
+		username: &str,
+		password: impl AsRef<str>,
+		plaintext: PlaintextPolicy,
+	) -> Result<bool, VerifyError> {
 		self.0
 			.get(username)
-			.map(|hash| hash.check(password))
-			.unwrap_or_default()
+			.ok_or(VerifyError::UnknownUser)?
+			.verify_with_policy(password, plaintext)
+	}
+
+	/// Verifies the user's password, taking as long for unknown users as for existing ones
+	///
+	/// See [`verify_without_enumeration`](#method.verify_without_enumeration).
+	pub fn check_without_enumeration(&self, username: &str, password: impl AsRef<str>) -> bool {
+		self.verify_without_enumeration(username, password, PlaintextPolicy::Reject)
+			.unwrap_or(false)
+	}
+
+	/// Verifies the user's password, without revealing through timing whether the user exists.
+	///
+	/// For unknown users the password is verified against a hash of a random password with the
+	/// most common scheme and cost of the file, and the result thrown away before returning
+	/// [`VerifyError::UnknownUser`](enum.VerifyError.html#variant.UnknownUser). The scheme is
+	/// picked from up to 32 entries on every call, so it takes the same time for large files.
+	///
+	/// ```
+	/// use htpasswd_verify::{PlaintextPolicy, VerifyError};
+	///
+	/// let htpasswd = htpasswd_verify::load("user:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa");
+	/// let policy = PlaintextPolicy::Reject;
+	/// assert_eq!(htpasswd.verify_without_enumeration("user", "password", policy), Ok(true));
+	/// assert_eq!(htpasswd.verify_without_enumeration("nobody", "password", policy), Err(VerifyError::UnknownUser));
+	/// ```
+	pub fn verify_without_enumeration(
+		&self,
+		username: &str,
+		password: impl AsRef<str>,
+		plaintext: PlaintextPolicy,
+	) -> Result<bool, VerifyError> {
+		let password = password.as_ref();
+		let dummy = std::hint::black_box(self.dummy_hash());
+		match self.0.get(username) {
+			Some(hash) => hash.verify_with_policy(password, plaintext),
+			None => {
+				if let Some(dummy) = dummy {
+					std::hint::black_box(dummy.verify_with_policy(password, plaintext)).ok();
+				}
+				Err(VerifyError::UnknownUser)
+			}
+		}
+	}
+
+	/// Hash of a random password with the most common scheme and cost among a sample of the
+	/// entries, which takes as long to verify as most users
+	pub(crate) fn dummy_hash(&self) -> Option<Arc<Hash<'static>>> {
+		self.dummy_template()?.hash()
+	}
+
+	/// Picks the scheme and cost of the dummy hash, without generating it
+	pub(crate) fn dummy_template(&self) -> Option<DummyTemplate> {
+		let mut classes: Vec<(_, usize, &Hash<'_>)> = Vec::new();
+		for hash in self.0.values().take(DUMMY_SAMPLE_LEN) {
+			let class = hash.timing_class();
+			match classes.iter_mut().find(|(other, _, _)| *other == class) {
+				Some((_, count, _)) => *count += 1,
+				None => classes.push((class, 1, hash)),
+			}
+		}
+		let ((scheme, cost, params), _, hash) =
+			classes.into_iter().max_by_key(|(_, count, _)| *count)?;
+		Some(DummyTemplate {
+			class: (scheme, cost, params.to_string()),
+			spec: hash.dummy_spec()?,
+		})
+	}
+
+	/// Copies the usernames and hashes so the result outlives the parsed string
+	///
+	/// ```
+	/// use htpasswd_verify::Htpasswd;
+	///
+	/// let data = String::from("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00");
+	/// let htpasswd: Htpasswd<'static> = Htpasswd::parse(&data).unwrap().into_owned();
+	/// drop(data);
+	/// assert!(htpasswd.check("user", "password"));
+	/// ```
+	pub fn into_owned(self) -> Htpasswd<'static> {
+		Htpasswd(
+			self.0
+				.into_iter()
+				.map(|(username, hash)| (owned(username), hash.into_owned()))
+				.collect(),
+		)
+	}
+}
+
+/// Timing class of a file and how to make a dummy hash for it, see `Htpasswd::dummy_template`
+#[derive(Debug)]
+pub(crate) struct DummyTemplate {
+	class: TimingClass,
+	spec: DummySpec,
+}
+
+impl DummyTemplate {
+	/// Dummy hash of the class, generated on first use, which can take as long as a verification
+	pub(crate) fn hash(&self) -> Option<Arc<Hash<'static>>> {
+		DUMMY_HASHES.get_or_generate(&self.class, || self.spec.generate())
+	}
+}
+
+/// Dummy hashes by timing class, at most `MAX_DUMMY_HASHES` of them, oldest first
+#[derive(Debug)]
+struct DummyHashes(RwLock<VecDeque<(TimingClass, Arc<Hash<'static>>)>>);
+
+impl DummyHashes {
+	fn get_or_generate(
+		&self,
+		class: &TimingClass,
+		generate: impl FnOnce() -> Option<Hash<'static>>,
+	) -> Option<Arc<Hash<'static>>> {
+		let find = |dummies: &VecDeque<(TimingClass, Arc<Hash<'static>>)>| {
+			dummies
+				.iter()
+				.find(|(other, _)| other == class)
+				.map(|(_, dummy)| Arc::clone(dummy))
+		};
+		if let Some(dummy) = find(&self.0.read().unwrap_or_else(PoisonError::into_inner)) {
+			return Some(dummy);
+		}
+		// Generated without the lock, it can take as long as a verification
+		let dummy = Arc::new(generate()?);
+		let mut dummies = self.0.write().unwrap_or_else(PoisonError::into_inner);
+		if let Some(dummy) = find(&dummies) {
+			return Some(dummy);
+		}
+		if dummies.len() >= MAX_DUMMY_HASHES {
+			dummies.pop_front();
+		}
+		dummies.push_back((class.clone(), Arc::clone(&dummy)));
+		Some(dummy)
+	}
+}
+
+impl<'a> Htpasswd<'a> {
+	/// Parses an htpasswd file, failing on the first malformed entry.
+	///
+	/// Blank lines and lines starting with `#` are skipped, like Apache does. Trailing whitespace
+	/// is trimmed from plaintext passwords and an error after a hash.
+	///
+	/// ```
+	/// use htpasswd_verify::{Htpasswd, ParseErrorKind};
+	///
+	/// let data = "user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00\nbroken:$apr1$lZL6";
+	/// let err = Htpasswd::parse(data).unwrap_err();
+	/// assert_eq!((err.line, err.column), (2, 14));
+	/// assert_eq!(err.username.as_deref(), Some("broken"));
+	/// assert_eq!(err.kind, ParseErrorKind::TruncatedApr1Salt);
+	/// ```
+	pub fn parse(bytes: &'a str) -> Result<Self, ParseError> {
+		let mut hashes = HashMap::new();
+		for (line_no, line) in entries(bytes) {
+			let (username, hash) =
+				try_parse_hash_entry(line).map_err(|(column, kind)| ParseError {
+					line: line_no,
+					column: column + 1,
+					username: entry_username(line),
+					kind,
+				})?;
+			if hashes.insert(username.into(), hash).is_some() {
+				return Err(ParseError {
+					line: line_no,
+					column: 1,
+					username: Some(username.to_string()),
+					kind: ParseErrorKind::DuplicateUsername,
+				});
+			}
+		}
+		Ok(Htpasswd(hashes))
+	}
+
+	/// Parses an htpasswd file like [`load`](fn.load.html), reporting everything that was
+	/// skipped or looks suspicious.
+	///
+	/// Trailing whitespace is trimmed from hashes. Malformed hashes are kept but never verify.
+	/// When a username has several entries, the first one is in effect, as in Apache.
+	///
+	/// ```
+	/// use htpasswd_verify::{DiagnosticKind, Htpasswd, ParseErrorKind};
+	///
+	/// let data = "user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00\nbroken:$apr1$lZL6";
+	/// let (htpasswd, diagnostics) = Htpasswd::parse_lenient(data);
+	/// assert!(htpasswd.check("user", "password"));
+	/// assert_eq!(diagnostics[0].line, 2);
+	/// let expected = DiagnosticKind::MalformedHash(ParseErrorKind::TruncatedApr1Salt);
+	/// assert_eq!(diagnostics[0].kind, expected);
+	/// ```
+	pub fn parse_lenient(bytes: &'a str) -> (Self, Vec<Diagnostic>) {
+		let mut hashes = HashMap::new();
+		let mut defined_on = HashMap::new();
+		let mut diagnostics = Vec::new();
+		for (line_no, line) in entries(bytes) {
+			let entry = match read_entry(line) {
+				Ok(entry) => entry,
+				Err((column, kind)) => {
+					diagnostics.push(Diagnostic {
+						line: line_no,
+						column: column + 1,
+						username: entry_username(line),
+						kind: DiagnosticKind::Skipped(kind),
+					});
+					continue;
+				}
+			};
+			let username = entry.username;
+			if let Some(column) = entry.trailing_whitespace {
+				diagnostics.push(Diagnostic {
+					line: line_no,
+					column: column + 1,
+					username: Some(username.to_string()),
+					kind: DiagnosticKind::TrailingWhitespace,
+				});
+			}
+			let suspicious = match (&entry.hash, entry.malformed) {
+				(_, Some((column, kind))) => Some((column, DiagnosticKind::MalformedHash(kind))),
+				(Hash::Plaintext(_), _) => Some((username.len() + 1, DiagnosticKind::Plaintext)),
+				(Hash::Unknown(_), _) => Some((username.len() + 1, DiagnosticKind::UnknownFormat)),
+				_ => None,
+			};
+			if let Some((column, kind)) = suspicious {
+				diagnostics.push(Diagnostic {
+					line: line_no,
+					column: column + 1,
+					username: Some(username.to_string()),
+					kind,
+				});
+			}
+			if let Some(&previous_line) = defined_on.get(username) {
+				diagnostics.push(Diagnostic {
+					line: line_no,
+					column: 1,
+					username: Some(username.to_string()),
+					kind: DiagnosticKind::DuplicateUsername { previous_line },
+				});
+				continue;
+			}
+			defined_on.insert(username, line_no);
+			hashes.insert(username.into(), entry.hash);
+		}
+		(Htpasswd(hashes), diagnostics)
 	}
 }
 
-pub fn load(bytes: &str) -> Htpasswd {
-	let lines = bytes.split('\n');
-	let hashes = lines
-		.filter_map(parse_hash_entry)
-		.collect::<HashMap<&str, Hash>>();
+/// Loads an htpasswd file, skipping entries without a `:` separator or a username.
+///
+/// Trailing whitespace is trimmed from hashes. Malformed hashes are kept but never verify, use
+/// [`try_load`](fn.try_load.html) to reject them. When a username has several entries, the first
+/// one is in effect, as in Apache.
+pub fn load(bytes: &str) -> Htpasswd<'_> {
+	let mut hashes = HashMap::new();
+	for (username, hash) in entries(bytes).filter_map(|(_, line)| parse_hash_entry(line)) {
+		hashes.entry(username.into()).or_insert(hash);
+	}
 	Htpasswd(hashes)
 }
 
-fn parse_hash_entry(entry: &str) -> Option<(&str, Hash)> {
-	let semicolon = match entry.find(':') {
-		Some(idx) => idx,
-		None => return None,
-	};
+/// Loads an htpasswd file, failing on the first malformed entry.
+///
+/// See [`Htpasswd::parse`](struct.Htpasswd.html#method.parse).
+pub fn try_load(bytes: &str) -> Result<Htpasswd<'_>, ParseError> {
+	Htpasswd::parse(bytes)
+}
+
+/// Loads an htpasswd file like [`load`](fn.load.html), reporting what was skipped or looks
+/// suspicious.
+///
+/// See [`Htpasswd::parse_lenient`](struct.Htpasswd.html#method.parse_lenient).
+pub fn load_with_diagnostics(bytes: &str) -> (Htpasswd<'_>, Vec<Diagnostic>) {
+	Htpasswd::parse_lenient(bytes)
+}
+
+/// Numbered lines of the file, without blank lines, `#` comments and `\r` line endings
+pub(crate) fn entries(bytes: &str) -> impl Iterator<Item = (usize, &str)> {
+	bytes
+		.split('\n')
+		.map(|line| line.strip_suffix('\r').unwrap_or(line))
+		.enumerate()
+		.map(|(idx, line)| (idx + 1, line))
+		.filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+}
+
+fn entry_username(entry: &str) -> Option<String> {
+	entry.find(':').map(|sep| entry[..sep].to_string())
+}
+
+/// Entry as read by [`load`](fn.load.html),
+/// [`load_with_diagnostics`](fn.load_with_diagnostics.html) and
+/// [`Htpasswd::parse`](struct.Htpasswd.html#method.parse)
+struct Entry<'a> {
+	username: &'a str,
+	hash: Hash<'a>,
+	/// Byte offset of the whitespace trimmed from the end of the line
+	trailing_whitespace: Option<usize>,
+	/// Byte offset and reason the hash failed to parse, the hash is then `Hash::Malformed`
+	malformed: Option<(usize, ParseErrorKind)>,
+}
+
+/// Reads an entry like Apache does, trimming trailing whitespace and keeping malformed hashes
+///
+/// Fails if the line has no `:` or no username.
+fn read_entry(line: &str) -> Result<Entry<'_>, (usize, ParseErrorKind)> {
+	let entry = line.trim_end();
+	let semicolon = entry
+		.find(':')
+		.ok_or((entry.len(), ParseErrorKind::MissingSeparator))?;
 	let username = &entry[..semicolon];
+	if username.is_empty() {
+		return Err((0, ParseErrorKind::EmptyUsername));
+	}
+
+	let hash_start = semicolon + 1;
+	let hash_id = &entry[hash_start..];
+	let (hash, malformed) = match parse_hash(hash_id) {
+		Ok(hash) => (hash, None),
+		Err((offset, kind)) => (
+			Hash::Malformed(hash_id.into(), kind),
+			Some((hash_start + offset, kind)),
+		),
+	};
+	Ok(Entry {
+		username,
+		hash,
+		trailing_whitespace: Some(entry.len()).filter(|&len| len != line.len()),
+		malformed,
+	})
+}
+
+pub(crate) fn parse_hash_entry(entry: &str) -> Option<(&str, Hash<'_>)> {
+	read_entry(entry)
+		.ok()
+		.map(|entry| (entry.username, entry.hash))
+}
 
-	let hash_id = &entry[(semicolon + 1)..];
-	Some((username, Hash::parse(hash_id)))
+/// Parses an entry, returning the byte offset of the problem on failure
+///
+/// Whitespace after a plaintext password is trimmed like Apache does, after anything else it's
+/// an error.
+fn try_parse_hash_entry(line: &str) -> Result<(&str, Hash<'_>), (usize, ParseErrorKind)> {
+	let entry = read_entry(line)?;
+	match (entry.trailing_whitespace, entry.malformed) {
+		(Some(column), _) if !matches!(entry.hash, Hash::Plaintext(_)) => {
+			Err((column, ParseErrorKind::TrailingWhitespace))
+		}
+		(_, Some(malformed)) => Err(malformed),
+		_ => Ok((entry.username, entry.hash)),
+	}
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
 	use super::*;
+	use std::cell::Cell;
 
-	static DATA: &'static str = "user2:$apr1$7/CTEZag$omWmIgXPJYoxB3joyuq4S/
+	thread_local! {
+		pub(crate) static CONSTANT_TIME_EQ_CALLS: Cell<usize> = const { Cell::new(0) };
+	}
+
+	/// Empty temporary directory, unique to the test process and `name`
+	pub(crate) fn test_dir(name: &str) -> std::path::PathBuf {
+		let dir =
+			std::env::temp_dir().join(format!("htpasswd-verify-{}-{}", std::process::id(), name));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	/// Runs `future` on a current-thread runtime next to a task that keeps yielding, returning
+	/// the output and the longest time that task went without being polled
+	#[cfg(feature = "tokio")]
+	pub(crate) fn longest_stall<F: std::future::Future>(
+		future: F,
+	) -> (F::Output, std::time::Duration) {
+		use std::sync::atomic::{AtomicBool, Ordering};
+		use std::time::{Duration, Instant};
+
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let done = Arc::new(AtomicBool::new(false));
+			let mut last = Instant::now();
+			let ticker = tokio::spawn({
+				let done = Arc::clone(&done);
+				async move {
+					let mut longest = Duration::ZERO;
+					while !done.load(Ordering::Relaxed) {
+						let now = Instant::now();
+						longest = longest.max(now - last);
+						last = now;
+						tokio::task::yield_now().await;
+					}
+					longest
+				}
+			});
+			let output = future.await;
+			done.store(true, Ordering::Relaxed);
+			(output, ticker.await.unwrap())
+		})
+	}
+
+	static DATA: &str = "user2:$apr1$7/CTEZag$omWmIgXPJYoxB3joyuq4S/
 user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00
 bcrypt_test:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa
 sha1_test:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=
@@ -187,4 +1196,595 @@ crypt_test:bGVh02xkuGli2";
 		let htpasswd = load(DATA);
 		assert_eq!(htpasswd.check("user_does_not_exist", "password"), false);
 	}
+
+	#[test]
+	fn try_load_valid() {
+		let htpasswd = try_load(DATA).unwrap();
+		assert!(htpasswd.check("user", "password"));
+		assert!(htpasswd.check("bcrypt_test", "password"));
+	}
+
+	#[test]
+	fn try_load_skips_comments_and_blank_lines() {
+		let data = "# admins\r\nuser:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00\r\n\r\n";
+		let htpasswd = try_load(data).unwrap();
+		assert_eq!(htpasswd.0.len(), 1);
+		assert!(htpasswd.check("user", "password"));
+	}
+
+	fn assert_parse_error(data: &str, line: usize, column: usize, kind: ParseErrorKind) {
+		let err = try_load(data).unwrap_err();
+		assert_eq!(
+			(err.line, err.column, err.kind),
+			(line, column, kind),
+			"{}",
+			data
+		);
+	}
+
+	#[test]
+	fn try_load_errors() {
+		assert_parse_error("user", 1, 5, ParseErrorKind::MissingSeparator);
+		assert_parse_error(":bGVh02xkuGli2", 1, 1, ParseErrorKind::EmptyUsername);
+		assert_parse_error("u:$apr1$lZL6", 1, 9, ParseErrorKind::TruncatedApr1Salt);
+		assert_parse_error(
+			"u:$apr1$lZL6V/ci$eIMz",
+			1,
+			18,
+			ParseErrorKind::InvalidApr1Hash,
+		);
+		assert_parse_error(
+			"u:{SHA}W6ph5Mm5Pz8Ggi",
+			1,
+			8,
+			ParseErrorKind::InvalidSha1Base64,
+		);
+		assert_parse_error(
+			"u:{SHA}cGFzc3dvcmQ=",
+			1,
+			8,
+			ParseErrorKind::InvalidSha1Length,
+		);
+		assert_parse_error(
+			"u:$2y$5$nC6nErr9XZ",
+			1,
+			7,
+			ParseErrorKind::MalformedBcryptCost,
+		);
+		assert_parse_error(
+			"u:$2y$05$nC6nErr9XZ",
+			1,
+			10,
+			ParseErrorKind::MalformedBcryptHash,
+		);
+		assert_parse_error("u:x\nu:x", 2, 1, ParseErrorKind::DuplicateUsername);
+		assert_parse_error(
+			"u:bGVh02xkuGli2 ",
+			1,
+			16,
+			ParseErrorKind::TrailingWhitespace,
+		);
+		assert_parse_error("u:$apr1$lZL6\t", 1, 13, ParseErrorKind::TrailingWhitespace);
+		let htpasswd = try_load("u:pass word ").unwrap();
+		assert!(htpasswd.check_with_policy("u", "pass word", PlaintextPolicy::AllowPlaintext));
+		assert!(!htpasswd.check_with_policy("u", "pass word ", PlaintextPolicy::AllowPlaintext));
+	}
+
+	#[test]
+	fn parse_error_username() {
+		let err = try_load("u:bGVh02xkuGli2\nbroken").unwrap_err();
+		assert_eq!(err.username, None);
+		let err = try_load("u:bGVh02xkuGli2\nbob:$apr1$lZL6").unwrap_err();
+		assert_eq!(err.username.as_deref(), Some("bob"));
+		assert_eq!(
+			err.to_string(),
+			"line 2, column 11: truncated apr1 salt (user \"bob\")"
+		);
+	}
+
+	#[test]
+	fn load_truncated_apr1_does_not_panic() {
+		let htpasswd = load("user:$apr1$lZL6\nuser2:$apr1$7/CTEZag$omWmIgXPJYoxB3joyuq4S/");
+		assert!(!htpasswd.check("user", "password"));
+		assert!(htpasswd.check("user2", "zaq1@WSX"));
+	}
+
+	#[test]
+	fn load_with_diagnostics_reports_problems() {
+		let data = "# comment
+user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00
+broken
+:bGVh02xkuGli2
+crypt_test:bGVh02xkuGli2 \t
+plain:password
+unknown:$9$c2FsdA$aGFzaA
+user:$apr1$7/CTEZag$omWmIgXPJYoxB3joyuq4S/";
+		let (htpasswd, diagnostics) = load_with_diagnostics(data);
+
+		assert!(htpasswd.check("crypt_test", "password"));
+		assert!(htpasswd.check("user", "password"));
+		assert!(!htpasswd.0.contains_key(""));
+		assert_eq!(
+			diagnostics
+				.iter()
+				.map(|d| (d.line, d.column, d.kind))
+				.collect::<Vec<_>>(),
+			vec![
+				(
+					3,
+					7,
+					DiagnosticKind::Skipped(ParseErrorKind::MissingSeparator)
+				),
+				(4, 1, DiagnosticKind::Skipped(ParseErrorKind::EmptyUsername)),
+				(5, 25, DiagnosticKind::TrailingWhitespace),
+				(6, 7, DiagnosticKind::Plaintext),
+				(7, 9, DiagnosticKind::UnknownFormat),
+				(8, 1, DiagnosticKind::DuplicateUsername { previous_line: 2 }),
+			]
+		);
+	}
+
+	#[test]
+	fn duplicates_use_first_entry() {
+		let data = "dup:bGVh02xkuGli2\ndup:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\ndup:bGVh02xkuGli2";
+		let (lenient, diagnostics) = load_with_diagnostics(data);
+		let document = HtpasswdDocument::parse(data).to_htpasswd();
+		for htpasswd in [load(data), lenient, document].iter() {
+			assert!(matches!(htpasswd.0["dup"], Hash::Crypt(_)));
+		}
+		assert_eq!(
+			diagnostics
+				.iter()
+				.map(|d| (d.line, d.kind))
+				.collect::<Vec<_>>(),
+			[
+				(2, DiagnosticKind::DuplicateUsername { previous_line: 1 }),
+				(3, DiagnosticKind::DuplicateUsername { previous_line: 1 }),
+			]
+		);
+	}
+
+	#[test]
+	fn load_agrees_with_load_with_diagnostics() {
+		let data = "u:bGVh02xkuGli2 \nv:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00\t\r\nw:$apr1$lZL6 \nw:bGVh02xkuGli2\n:bGVh02xkuGli2\nbroken";
+		let loaded = load(data);
+		let (lenient, diagnostics) = load_with_diagnostics(data);
+		let entries = |htpasswd: &Htpasswd<'_>| {
+			let mut entries = htpasswd
+				.0
+				.iter()
+				.map(|(username, hash)| (username.to_string(), hash.to_string()))
+				.collect::<Vec<_>>();
+			entries.sort();
+			entries
+		};
+		assert_eq!(entries(&loaded), entries(&lenient));
+		assert_eq!(loaded.0.len(), 3);
+		assert!(loaded.check("u", "password"));
+		assert!(loaded.check("v", "password"));
+		assert_eq!(
+			loaded.verify("w", "password"),
+			Err(VerifyError::MalformedHash)
+		);
+		assert_eq!(
+			diagnostics
+				.iter()
+				.map(|d| (d.line, d.column, d.kind))
+				.collect::<Vec<_>>(),
+			[
+				(1, 16, DiagnosticKind::TrailingWhitespace),
+				(2, 40, DiagnosticKind::TrailingWhitespace),
+				(3, 13, DiagnosticKind::TrailingWhitespace),
+				(
+					3,
+					9,
+					DiagnosticKind::MalformedHash(ParseErrorKind::TruncatedApr1Salt)
+				),
+				(4, 1, DiagnosticKind::DuplicateUsername { previous_line: 3 }),
+				(5, 1, DiagnosticKind::Skipped(ParseErrorKind::EmptyUsername)),
+				(
+					6,
+					7,
+					DiagnosticKind::Skipped(ParseErrorKind::MissingSeparator)
+				),
+			]
+		);
+	}
+
+	#[test]
+	fn load_with_diagnostics_clean_file() {
+		let (htpasswd, diagnostics) = load_with_diagnostics(DATA);
+		assert!(diagnostics.is_empty());
+		assert_eq!(htpasswd.0.len(), 5);
+	}
+
+	#[test]
+	fn parse_plaintext_and_unknown() {
+		assert!(matches!(Hash::parse("bGVh02xkuGli2"), Hash::Crypt(_)));
+		assert!(matches!(Hash::parse("password"), Hash::Plaintext(_)));
+		assert!(matches!(Hash::parse("bGVh02xkuGli2x"), Hash::Plaintext(_)));
+		assert!(matches!(Hash::parse("$password"), Hash::Plaintext(_)));
+		assert!(matches!(Hash::parse("$9$salt$hash"), Hash::Unknown(_)));
+		assert!(matches!(Hash::parse("{PBKDF2}c2FsdA=="), Hash::Unknown(_)));
+		for hash in ["$5$salt$hash", "{SSHA}aGFzaA==", "$apr1$lZL6"].iter() {
+			match Hash::parse(hash) {
+				Hash::Malformed(raw, kind) => {
+					assert_eq!(raw, *hash);
+					assert_eq!(Hash::try_parse(hash).err(), Some(kind));
+				}
+				other => panic!("{} parsed as {:?}", hash, other),
+			}
+		}
+	}
+
+	#[test]
+	fn plaintext_policy() {
+		let htpasswd = load("plain:password\nunknown:$5$password");
+		assert!(!htpasswd.check("plain", "password"));
+		assert!(htpasswd.check_with_policy("plain", "password", PlaintextPolicy::AllowPlaintext));
+		assert!(!htpasswd.check_with_policy("plain", "passwort", PlaintextPolicy::AllowPlaintext));
+		assert!(!htpasswd.check_with_policy(
+			"unknown",
+			"$5$password",
+			PlaintextPolicy::AllowPlaintext
+		));
+	}
+
+	#[test]
+	fn bcrypt_versions() {
+		let digest = "$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa";
+		for &version in BCryptVersion::ALL.iter() {
+			let entry = format!("{}{}", version.prefix(), &digest[1..]);
+			let hash = Hash::try_parse(&entry).unwrap();
+			match &hash {
+				Hash::BCrypt(bcrypt) => {
+					assert_eq!(bcrypt.version, version);
+					assert_eq!(bcrypt.cost, 5);
+					assert_eq!(bcrypt.salt, "nC6nErr9XZJuMJ57WyCob.");
+					assert_eq!(bcrypt.to_string(), entry);
+				}
+				_ => panic!("{} not parsed as bcrypt", entry),
+			}
+			assert!(hash.check("password"), "{}", entry);
+		}
+	}
+
+	#[test]
+	fn bcrypt_unknown_version() {
+		let hash = "$2z$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa";
+		assert!(matches!(Hash::parse(hash), Hash::Unknown(_)));
+	}
+
+	#[test]
+	fn hash_display_round_trip() {
+		for line in DATA
+			.lines()
+			.chain(["u:password", "u:$5$salt$hash"].iter().copied())
+		{
+			let hash = &line[(line.find(':').unwrap() + 1)..];
+			assert_eq!(Hash::parse(hash).to_string(), hash);
+		}
+	}
+
+	#[test]
+	fn verify_errors() {
+		let htpasswd = load(
+			"bad_bcrypt:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa!
+bad_sha1:{SHA}W6ph5Mm5Pz8Ggi
+plain:password
+malformed:$5$salt$hash
+unknown:$9$salt$hash",
+		);
+		assert_eq!(
+			htpasswd.verify("nobody", "password"),
+			Err(VerifyError::UnknownUser)
+		);
+		assert_eq!(
+			htpasswd.verify("bad_bcrypt", "password"),
+			Err(VerifyError::MalformedHash)
+		);
+		assert_eq!(
+			htpasswd.verify("bad_sha1", "password"),
+			Err(VerifyError::MalformedHash)
+		);
+		assert_eq!(
+			htpasswd.verify("malformed", "password"),
+			Err(VerifyError::MalformedHash)
+		);
+		assert_eq!(
+			htpasswd.verify("plain", "password"),
+			Err(VerifyError::PlaintextRejected)
+		);
+		assert_eq!(
+			htpasswd.verify("unknown", "password"),
+			Err(VerifyError::UnsupportedFormat)
+		);
+		assert!(!htpasswd.check("bad_bcrypt", "password"));
+	}
+
+	#[test]
+	fn verify_malformed_hash_material() {
+		let bcrypt = Hash::BCrypt(BCryptHash {
+			version: BCryptVersion::TwoY,
+			cost: 5,
+			salt: "nC6nErr9XZ".into(),
+			hash: "EgL1I2jCVa".into(),
+		});
+		assert_eq!(bcrypt.verify("password"), Err(VerifyError::MalformedHash));
+		assert!(!bcrypt.check("password"));
+		assert_eq!(
+			Hash::SHA1("W6ph5Mm5Pz8Ggi!".into()).verify("password"),
+			Err(VerifyError::MalformedHash)
+		);
+		assert_eq!(
+			Hash::Crypt("bGVh02".into()).verify("password"),
+			Err(VerifyError::MalformedHash)
+		);
+		let expensive = Hash::Sha512Crypt(ShaCryptHash {
+			rounds: Some(999_999_999),
+			salt: "saltstring".into(),
+			hash: "svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1".into(),
+		});
+		assert_eq!(expensive.verify("password"), Err(VerifyError::CostTooHigh));
+		assert!(!expensive.check("password"));
+	}
+
+	#[test]
+	fn sha_crypt_verify() {
+		let htpasswd = load(
+			"sha256:$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5
+sha256_rounds:$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA
+sha256_long_salt:$5$rounds=10000$saltstringsaltstring$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA
+sha512:$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1
+sha512_rounds:$6$rounds=10000$saltstringsaltst$OW1/O6BYHV6BcXZu8QVeXbDWra3Oeqh0sbHbbMCVNSnCM/UrjmM0Dp8vOuZeHBy/YTBmSK6H9qs/y3RnOaw5v.",
+		);
+		assert!(htpasswd.check("sha256", "Hello world!"));
+		assert!(htpasswd.check("sha256_rounds", "Hello world!"));
+		assert!(htpasswd.check("sha256_long_salt", "Hello world!"));
+		assert!(htpasswd.check("sha512", "Hello world!"));
+		assert!(htpasswd.check("sha512_rounds", "Hello world!"));
+		assert!(!htpasswd.check("sha256", "hello world!"));
+		assert!(!htpasswd.check("sha512_rounds", "hello world!"));
+		match &htpasswd.0["sha256_rounds"] {
+			Hash::Sha256Crypt(hash) => {
+				assert_eq!(hash.rounds, Some(10000));
+				assert_eq!(hash.salt, "saltstringsaltst");
+			}
+			hash => panic!("unexpected hash {:?}", hash),
+		}
+		// Parses like glibc does, but is too expensive to verify
+		let htpasswd = try_load("u:$6$rounds=5000000$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1").unwrap();
+		assert_eq!(
+			htpasswd.verify("u", "Hello world!"),
+			Err(VerifyError::CostTooHigh)
+		);
+	}
+
+	#[test]
+	fn sha_crypt_parse_errors() {
+		assert_parse_error(
+			"u:$5$rounds=x$salt$",
+			1,
+			6,
+			ParseErrorKind::MalformedShaCryptRounds,
+		);
+		assert_parse_error(
+			"u:$6$rounds=10$salt$",
+			1,
+			6,
+			ParseErrorKind::MalformedShaCryptRounds,
+		);
+		assert_parse_error(
+			"u:$6$rounds=1000000000$salt$",
+			1,
+			6,
+			ParseErrorKind::MalformedShaCryptRounds,
+		);
+		assert_parse_error(
+			"u:$5$saltstring",
+			1,
+			6,
+			ParseErrorKind::TruncatedShaCryptSalt,
+		);
+		assert_parse_error(
+			"u:$5$rounds=1000$s$abc",
+			1,
+			20,
+			ParseErrorKind::InvalidShaCryptHash,
+		);
+	}
+
+	#[test]
+	fn md5_crypt_verify() {
+		let htpasswd = try_load("md5:$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/").unwrap();
+		match &htpasswd.0["md5"] {
+			Hash::Md5Crypt(hash) => assert_eq!(hash.salt, "saltsalt"),
+			hash => panic!("unexpected hash {:?}", hash),
+		}
+		assert!(htpasswd.check("md5", "password"));
+		assert!(!htpasswd.check("md5", "passwort"));
+		assert_eq!(
+			htpasswd.0["md5"].to_string(),
+			"$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/"
+		);
+		assert_parse_error("u:$1$saltsaltx$", 1, 6, ParseErrorKind::TruncatedApr1Salt);
+	}
+
+	#[test]
+	fn verify_compares_in_constant_time() {
+		let hashes = [
+			"$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00",
+			"$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/",
+			"$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa",
+			"{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=",
+			"bGVh02xkuGli2",
+			"$5$saltsalt$gOjOtoMpVhru2uyjeJSEc/JaLQWOXMNmlOnj6T4AtC.",
+			"{SSHA}yrht1iYXEIkejLVu42JWkadd80RzYWx0c2FsdA==",
+			"{CRYPT}bGVh02xkuGli2",
+			"$argon2id$v=19$m=256,t=2,p=1$c29tZXNhbHQ$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4",
+			"$scrypt$ln=4,r=8,p=1$c2FsdHNhbHRzYWx0c2FsdA$5f/Vi+XRWGUNGScbsma6KJ4zLFIke/NJsrvr7lQLAyA",
+			"password",
+		];
+		for &hash in hashes.iter() {
+			let hash = Hash::try_parse(hash).unwrap();
+			for &(password, expected) in [("password", true), ("passwort", false)].iter() {
+				CONSTANT_TIME_EQ_CALLS.with(|calls| calls.set(0));
+				assert_eq!(
+					hash.verify_with_policy(password, PlaintextPolicy::AllowPlaintext),
+					Ok(expected),
+					"{}",
+					hash
+				);
+				assert_eq!(CONSTANT_TIME_EQ_CALLS.with(Cell::get), 1, "{}", hash);
+			}
+		}
+	}
+
+	#[test]
+	fn constant_time_eq_empty() {
+		assert!(constant_time_eq(b"", b""));
+		assert!(!constant_time_eq(b"", b"a"));
+		assert!(!constant_time_eq(b"a", b""));
+		assert!(constant_time_eq(b"abc", b"abc"));
+		assert!(!constant_time_eq(b"abc", b"abd"));
+		let htpasswd = load("user:");
+		assert!(htpasswd.check_with_policy("user", "", PlaintextPolicy::AllowPlaintext));
+		assert!(!htpasswd.check_with_policy("user", "x", PlaintextPolicy::AllowPlaintext));
+	}
+
+	#[test]
+	fn verify_without_enumeration_runs_dummy() {
+		let htpasswd = load(
+			"bcrypt_test:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa
+bcrypt_other:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa
+bcrypt_cheap:$2y$04$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa
+user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00",
+		);
+		assert!(matches!(
+			htpasswd.dummy_hash().as_deref(),
+			Some(Hash::BCrypt(BCryptHash { cost: 5, .. }))
+		));
+		for &username in ["nobody", "user"].iter() {
+			CONSTANT_TIME_EQ_CALLS.with(|calls| calls.set(0));
+			htpasswd.check_without_enumeration(username, "password");
+			assert_eq!(CONSTANT_TIME_EQ_CALLS.with(Cell::get), 1, "{}", username);
+		}
+		assert!(htpasswd.check_without_enumeration("user", "password"));
+		assert!(!htpasswd.check_without_enumeration("user", "passwort"));
+		assert!(!htpasswd.check_without_enumeration("nobody", "password"));
+		assert_eq!(
+			htpasswd.verify_without_enumeration("nobody", "password", PlaintextPolicy::Reject),
+			Err(VerifyError::UnknownUser)
+		);
+		assert!(load("").dummy_hash().is_none());
+
+		// Follows changes to the map, and files with the same scheme share one dummy
+		let mut htpasswd = load("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00");
+		assert!(matches!(
+			htpasswd.dummy_hash().as_deref(),
+			Some(Hash::MD5(_))
+		));
+		htpasswd.0.clear();
+		assert!(htpasswd.dummy_hash().is_none());
+		htpasswd.insert_hash("crypt", "bGVh02xkuGli2").unwrap();
+		let other = load("other:bGVh02xkuGli2");
+		assert!(Arc::ptr_eq(
+			&htpasswd.dummy_hash().unwrap(),
+			&other.dummy_hash().unwrap()
+		));
+		assert!(!load("").check_without_enumeration("nobody", "password"));
+	}
+
+	#[test]
+	fn dummy_hash_is_synthetic() {
+		let htpasswd = load("user:secret-dummy-test\nother:hunter2-dummy-test");
+		match htpasswd.dummy_hash().as_deref() {
+			Some(Hash::Plaintext(dummy)) => {
+				assert_ne!(dummy, "secret-dummy-test");
+				assert_ne!(dummy, "hunter2-dummy-test");
+			}
+			dummy => panic!("unexpected dummy {:?}", dummy),
+		}
+		let data = "a:$2y$04$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa
+b:$argon2id$v=19$m=256,t=3,p=1$c29tZXNhbHQ$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4
+c:$5$rounds=1500$saltsalt$gOjOtoMpVhru2uyjeJSEc/JaLQWOXMNmlOnj6T4AtC.";
+		for line in data.lines() {
+			let original = Hash::parse(&line[2..]);
+			let dummy = load(line).dummy_hash().unwrap();
+			assert_eq!(dummy.timing_class(), original.timing_class());
+			assert_ne!(dummy.to_string(), original.to_string());
+		}
+	}
+
+	#[test]
+	fn dummy_hashes_are_bounded() {
+		let dummies = DummyHashes(RwLock::default());
+		let class = |cost| ("bcrypt", cost, String::new());
+		let first = dummies
+			.get_or_generate(&class(0), || Some(Hash::Unknown("".into())))
+			.unwrap();
+		let again = dummies
+			.get_or_generate(&class(0), || unreachable!())
+			.unwrap();
+		assert!(Arc::ptr_eq(&first, &again));
+		assert!(dummies.get_or_generate(&class(1), || None).is_none());
+
+		for cost in 1..=MAX_DUMMY_HASHES as u32 {
+			dummies.get_or_generate(&class(cost), || Some(Hash::Unknown("".into())));
+		}
+		let kept = dummies.0.read().unwrap();
+		assert_eq!(kept.len(), MAX_DUMMY_HASHES);
+		assert!(kept.iter().all(|(other, _)| *other != class(0)));
+	}
+
+	#[test]
+	fn timing_class_ignores_salt() {
+		let first = Hash::parse(
+			"$argon2id$v=19$m=256,t=2,p=1$c29tZXNhbHQ$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4",
+		);
+		let second = Hash::parse(
+			"$argon2id$v=19$m=256,t=2,p=1$b3RoZXJzYWx0$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4",
+		);
+		let cheaper = Hash::parse(
+			"$argon2id$v=19$m=256,t=1,p=1$c29tZXNhbHQ$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4",
+		);
+		assert_eq!(first.timing_class(), second.timing_class());
+		assert_eq!(first.timing_class().2, "$argon2id$v=19$m=256,t=2,p=1");
+		assert_ne!(first.timing_class(), cheaper.timing_class());
+		assert_eq!(
+			Hash::parse("{CRYPT}$6$rounds=10000$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/").timing_class(),
+			("sha512-crypt", 10000, "")
+		);
+	}
+
+	#[test]
+	fn load_agrees_with_try_load() {
+		let data = "u:bGVh02xkuGli2\r\n#c:bGVh02xkuGli2\r\n\r\n";
+		let loaded = load(data);
+		assert_eq!(loaded.0.len(), 1);
+		assert!(loaded.check("u", "password"));
+		assert!(!loaded.check("#c", "password"));
+		assert!(try_load(data).unwrap().check("u", "password"));
+	}
+
+	#[test]
+	fn into_owned_outlives_source() {
+		let data = format!(
+			"{}\nldap:{{SSHA}}yrht1iYXEIkejLVu42JWkadd80RzYWx0c2FsdA==",
+			DATA
+		);
+		let htpasswd = std::sync::Arc::new(try_load(&data).unwrap().into_owned());
+		drop(data);
+		let shared = std::sync::Arc::clone(&htpasswd);
+		let checked = std::thread::spawn(move || {
+			shared.check("user", "password")
+				&& shared.check("bcrypt_test", "password")
+				&& shared.check("ldap", "password")
+		});
+		assert!(checked.join().unwrap());
+		assert!(htpasswd
+			.0
+			.keys()
+			.all(|username| matches!(username, Cow::Owned(_))));
+	}
 }
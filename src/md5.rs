@@ -60,9 +60,14 @@
 
 #![allow(clippy::many_single_char_names)]
 
+use std::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
 pub(crate) const APR1_ID: &str = "$apr1$";
+pub(crate) const MD5_CRYPT_ID: &str = "$1$";
 
-const DIGEST_SIZE: usize = 16;
+/// Size of an MD5 digest in bytes
+pub const DIGEST_SIZE: usize = 16;
 
 const S11: u32 = 7;
 const S12: u32 = 12;
@@ -120,12 +125,30 @@ fn ii(a: &mut u32, b: u32, c: u32, d: u32, x: u32, s: u32, ac: u32) {
 	*a = a.wrapping_add(b);
 }
 
+/// MD5 state, which is wiped when finalized or dropped since it's derived from the password
+#[derive(Clone)]
 struct MD5Ctx {
 	state: [u32; 4],
 	count: [u32; 2],
 	buffer: [u8; 64],
 }
 
+impl Zeroize for MD5Ctx {
+	fn zeroize(&mut self) {
+		self.state.zeroize();
+		self.count.zeroize();
+		self.buffer.zeroize();
+	}
+}
+
+impl Drop for MD5Ctx {
+	fn drop(&mut self) {
+		self.zeroize();
+	}
+}
+
+impl ZeroizeOnDrop for MD5Ctx {}
+
 impl MD5Ctx {
 	fn new() -> Self {
 		MD5Ctx {
@@ -142,13 +165,15 @@ impl MD5Ctx {
 
 	fn update_buffer(&mut self, input: &[u8], input_len: usize) {
 		let mut i;
-		let mut idx = ((self.count[0] >> 3) & 0x3F) as u32;
+		let mut idx = (self.count[0] >> 3) & 0x3F;
 
-		self.count[0] += (input_len << 3) as u32;
-		if self.count[0] < ((input_len << 3) as u32) {
-			self.count[1] += 1;
+		// The bit count is kept modulo 2^64 like in the reference implementation
+		let bits = (input_len as u64).wrapping_shl(3);
+		self.count[0] = self.count[0].wrapping_add(bits as u32);
+		if self.count[0] < bits as u32 {
+			self.count[1] = self.count[1].wrapping_add(1);
 		}
-		self.count[1] += (input_len >> 29) as u32;
+		self.count[1] = self.count[1].wrapping_add((input_len as u64 >> 29) as u32);
 
 		let part_len = 64 - idx as usize;
 
@@ -187,16 +212,139 @@ impl MD5Ctx {
 
 		encode(digest, &self.state, DIGEST_SIZE);
 
-		self.state = [0; 4];
-		self.count = [0, 0];
-		self.buffer = [0; 64];
+		self.zeroize();
+	}
+}
+
+/// Streaming MD5 hasher
+///
+/// Plain RFC 1321 MD5, for formats like htdigest and `{MD5}` that need the raw digest. The state
+/// is wiped on [`finalize`](#method.finalize) and on drop. With the `digest` feature it also
+/// implements [`digest::Digest`](https://docs.rs/digest/0.10/digest/trait.Digest.html).
+///
+/// ```
+/// use htpasswd_verify::md5::Md5;
+///
+/// let mut hasher = Md5::new();
+/// hasher.update("message ");
+/// hasher.update(b"digest");
+/// assert_eq!(hasher.finalize(), Md5::digest("message digest"));
+/// assert_eq!(Md5::hex_digest("message digest"), "f96b697d7cb7938d525a2f31aaf161d0");
+/// ```
+#[derive(Clone)]
+pub struct Md5 {
+	ctx: MD5Ctx,
+}
+
+impl Md5 {
+	pub fn new() -> Self {
+		Md5 { ctx: MD5Ctx::new() }
+	}
+
+	pub fn update(&mut self, data: impl AsRef<[u8]>) {
+		let data = data.as_ref();
+		self.ctx.update_buffer(data, data.len());
+	}
+
+	pub fn finalize(mut self) -> [u8; DIGEST_SIZE] {
+		self.finalize_reset()
+	}
+
+	/// Returns the digest and starts over with an empty message
+	pub fn finalize_reset(&mut self) -> [u8; DIGEST_SIZE] {
+		let mut digest = [0; DIGEST_SIZE];
+		self.ctx.md5_final(&mut digest);
+		self.reset();
+		digest
+	}
+
+	pub fn reset(&mut self) {
+		self.ctx.zeroize();
+		self.ctx.init();
+	}
+
+	/// Digest of a whole message
+	pub fn digest(data: impl AsRef<[u8]>) -> [u8; DIGEST_SIZE] {
+		let mut hasher = Md5::new();
+		hasher.update(data);
+		hasher.finalize()
+	}
+
+	/// Digest of a whole message as lowercase hex, like `md5sum` prints it
+	pub fn hex_digest(data: impl AsRef<[u8]>) -> String {
+		Md5::digest(data)
+			.iter()
+			.map(|byte| format!("{:02x}", byte))
+			.collect()
+	}
+}
+
+impl Default for Md5 {
+	fn default() -> Self {
+		Md5::new()
+	}
+}
+
+impl fmt::Debug for Md5 {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("Md5 { .. }")
+	}
+}
+
+impl Zeroize for Md5 {
+	fn zeroize(&mut self) {
+		self.ctx.zeroize();
+	}
+}
+
+impl ZeroizeOnDrop for Md5 {}
+
+#[cfg(feature = "digest")]
+mod digest_impl {
+	use super::Md5;
+	use digest::{
+		consts::U16, FixedOutput, FixedOutputReset, HashMarker, Output, OutputSizeUser, Reset,
+		Update,
+	};
+
+	impl HashMarker for Md5 {}
+
+	impl OutputSizeUser for Md5 {
+		type OutputSize = U16;
+	}
+
+	impl Update for Md5 {
+		fn update(&mut self, data: &[u8]) {
+			Md5::update(self, data);
+		}
+	}
+
+	impl FixedOutput for Md5 {
+		fn finalize_into(mut self, out: &mut Output<Self>) {
+			out.copy_from_slice(&self.finalize_reset());
+		}
+	}
+
+	impl Reset for Md5 {
+		fn reset(&mut self) {
+			Md5::reset(self);
+		}
+	}
+
+	impl FixedOutputReset for Md5 {
+		fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+			out.copy_from_slice(&self.finalize_reset());
+		}
 	}
 }
 
 fn md5_transform(state: &mut [u32; 4], block: &[u8]) {
 	let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
 
-	let x = unsafe { std::slice::from_raw_parts(block.as_ptr() as *const u32, block.len() / 4) };
+	// Decoded byte by byte, so the block doesn't have to be aligned and the words are
+	// little-endian on every target
+	let mut x = Zeroizing::new([0u32; 16]);
+	decode(&mut *x, block, 64);
 
 	/* Round 1 */
 	ff(&mut a, b, c, d, x[0], S11, 0xd76aa478); /* 1 */
@@ -286,7 +434,6 @@ fn encode(output: &mut [u8], input: &[u32], len: usize) {
 	}
 }
 
-#[allow(dead_code)]
 fn decode(output: &mut [u32], input: &[u8], len: usize) {
 	for (i, j) in (0..len).step_by(4).enumerate() {
 		output[i] = input[j] as u32
@@ -331,16 +478,30 @@ fn to_64(s: &mut [u8], mut v: u64, n: i32) {
 /// Calculates apache specific md5 hash
 /// Returns just the hashed password, use [format_hash](fn.format_hash.html) to get the hash in htpasswd format
 pub fn md5_apr1_encode(pw: &str, salt: &str) -> String {
+	md5_crypt_encode(pw, salt, APR1_ID)
+}
+
+/// Calculates FreeBSD md5 crypt hash with the given magic prefix, `$1$` for the standard one
+/// found in `/etc/shadow` and `$apr1$` for the apache one
+/// Returns just the hashed password, use [format_hash_with](fn.format_hash_with.html) to get the full hash
+///
+/// ```
+/// use htpasswd_verify::md5::{format_hash_with, md5_crypt_encode};
+///
+/// let hash = md5_crypt_encode("password", "saltsalt", "$1$");
+/// assert_eq!(format_hash_with("$1$", &hash, "saltsalt"), "$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/");
+/// ```
+pub fn md5_crypt_encode(pw: &str, salt: &str, magic: &str) -> String {
 	let mut sp = salt.as_bytes();
 	let pw = pw.as_bytes();
 
-	if sp.starts_with(APR1_ID.as_bytes()) {
-		sp = &sp[APR1_ID.len()..sp.len()];
+	if sp.starts_with(magic.as_bytes()) {
+		sp = &sp[magic.len()..sp.len()];
 	}
 
 	let mut ctx = MD5Ctx::new();
 	ctx.update_buffer(pw, pw.len());
-	ctx.update_buffer(APR1_ID.as_bytes(), APR1_ID.len());
+	ctx.update_buffer(magic.as_bytes(), magic.len());
 	ctx.update_buffer(sp, sp.len());
 
 	let mut ctx1 = MD5Ctx::new();
@@ -348,19 +509,19 @@ pub fn md5_apr1_encode(pw: &str, salt: &str) -> String {
 	ctx1.update_buffer(sp, sp.len());
 	ctx1.update_buffer(pw, pw.len());
 
-	let mut digest = [0u8; DIGEST_SIZE];
+	let mut digest = Zeroizing::new([0u8; DIGEST_SIZE]);
 	ctx1.md5_final(&mut digest);
 
 	for pl in (1..(pw.len() + 1)).rev().step_by(DIGEST_SIZE) {
-		ctx.update_buffer(&digest, if pl > DIGEST_SIZE { DIGEST_SIZE } else { pl });
+		ctx.update_buffer(&*digest, if pl > DIGEST_SIZE { DIGEST_SIZE } else { pl });
 	}
 
-	digest = [0u8; DIGEST_SIZE];
+	digest.zeroize();
 
 	let mut i = pw.len();
 	while i != 0 {
 		if i & 1 != 0 {
-			ctx.update_buffer(&digest, 1);
+			ctx.update_buffer(&*digest, 1);
 		} else {
 			ctx.update_buffer(pw, 1);
 		}
@@ -375,7 +536,7 @@ pub fn md5_apr1_encode(pw: &str, salt: &str) -> String {
 		if i & 1 != 0 {
 			ctx1.update_buffer(pw, pw.len());
 		} else {
-			ctx1.update_buffer(&digest, DIGEST_SIZE);
+			ctx1.update_buffer(&*digest, DIGEST_SIZE);
 		}
 		if i % 3 != 0 {
 			ctx1.update_buffer(sp, sp.len());
@@ -386,14 +547,14 @@ pub fn md5_apr1_encode(pw: &str, salt: &str) -> String {
 		}
 
 		if i & 1 != 0 {
-			ctx1.update_buffer(&digest, DIGEST_SIZE);
+			ctx1.update_buffer(&*digest, DIGEST_SIZE);
 		} else {
 			ctx1.update_buffer(pw, pw.len());
 		}
 		ctx1.md5_final(&mut digest);
 	}
 
-	let mut digest_final: [u32; 16] = [0; 16];
+	let mut digest_final = Zeroizing::new([0u32; 16]);
 	digest
 		.iter()
 		.enumerate()
@@ -403,11 +564,141 @@ pub fn md5_apr1_encode(pw: &str, salt: &str) -> String {
 }
 
 pub fn format_hash(password: &str, salt: &str) -> String {
-	format!("{}{}${}", APR1_ID, salt, password)
+	format_hash_with(APR1_ID, password, salt)
+}
+
+pub fn format_hash_with(magic: &str, password: &str, salt: &str) -> String {
+	format!("{}{}${}", magic, salt, password)
 }
 
-/// Assumes the hash is in the correct format - $apr1$salt$password
+/// Verifies the password against an `$apr1$salt$password` hash, comparing in constant time.
+///
+/// Fails if the hash isn't a well-formed `$apr1$` hash.
 pub fn verify_apr1_hash(hash: &str, password: &str) -> Result<bool, &'static str> {
-	let salt = &hash[6..14];
-	Ok(format_hash(&md5_apr1_encode(password, salt), salt) == hash)
+	match crate::Hash::try_parse(hash) {
+		Ok(hash @ crate::Hash::MD5(_)) => hash.verify(password).map_err(|_| "malformed hash"),
+		_ => Err("not an $apr1$ hash"),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	static RFC_1321_VECTORS: &[(&str, &str)] = &[
+		("", "d41d8cd98f00b204e9800998ecf8427e"),
+		("a", "0cc175b9c0f1b6a831c399e269772661"),
+		("abc", "900150983cd24fb0d6963f7d28e17f72"),
+		("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+		(
+			"abcdefghijklmnopqrstuvwxyz",
+			"c3fcd3d76192e4007dfb496cca67e13b",
+		),
+		(
+			"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+			"d174ab98d277d9f5a5611c2c9f419d9f",
+		),
+		(
+			"12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+			"57edf4a22be3c955ac49da2e2107b67a",
+		),
+	];
+
+	fn digest_of(input: &[u8]) -> [u8; DIGEST_SIZE] {
+		Md5::digest(input)
+	}
+
+	fn hex(digest: &[u8]) -> String {
+		digest.iter().map(|b| format!("{:02x}", b)).collect()
+	}
+
+	#[test]
+	fn rfc_1321_vectors() {
+		for (input, expected) in RFC_1321_VECTORS {
+			assert_eq!(Md5::hex_digest(input), *expected);
+
+			let mut hasher = Md5::new();
+			for byte in input.bytes() {
+				hasher.update([byte]);
+			}
+			assert_eq!(hex(&hasher.finalize_reset()), *expected);
+			hasher.update(input);
+			assert_eq!(hex(&hasher.clone().finalize()), *expected);
+			assert_eq!(hex(&hasher.finalize()), *expected);
+		}
+	}
+
+	#[cfg(feature = "digest")]
+	#[test]
+	fn digest_trait() {
+		fn hex_digest<D: digest::Digest>(input: &str) -> String {
+			hex(&D::digest(input.as_bytes()))
+		}
+
+		for (input, expected) in RFC_1321_VECTORS {
+			assert_eq!(hex_digest::<Md5>(input), *expected);
+		}
+	}
+
+	#[test]
+	fn misaligned_input() {
+		let input =
+			b"12345678901234567890123456789012345678901234567890123456789012345678901234567890";
+		// Whole blocks are transformed straight from the input, so shifting it covers every
+		// alignment of the block pointer
+		let mut buf = vec![0u8; input.len() + 8];
+		for offset in 0..8 {
+			buf[offset..offset + input.len()].copy_from_slice(input);
+			assert_eq!(
+				hex(&digest_of(&buf[offset..offset + input.len()])),
+				"57edf4a22be3c955ac49da2e2107b67a"
+			);
+		}
+
+		let mut ctx = MD5Ctx::new();
+		for chunk in input[1..].chunks(13) {
+			ctx.update_buffer(chunk, chunk.len());
+		}
+		let mut digest = [0; DIGEST_SIZE];
+		ctx.md5_final(&mut digest);
+		assert_eq!(digest, digest_of(&input[1..]));
+		assert_eq!(
+			md5_apr1_encode("password", "lZL6V/ci"),
+			"eIMz/iKDkbtys/uU7LEK00"
+		);
+	}
+
+	#[test]
+	fn verify_apr1_hash_rejects_malformed() {
+		crate::tests::CONSTANT_TIME_EQ_CALLS.with(|calls| calls.set(0));
+		assert_eq!(
+			verify_apr1_hash("$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00", "passwort"),
+			Ok(false)
+		);
+		assert_eq!(
+			crate::tests::CONSTANT_TIME_EQ_CALLS.with(|calls| calls.get()),
+			1
+		);
+		assert!(verify_apr1_hash("$apr1$x", "pw").is_err());
+		assert!(verify_apr1_hash("$apr1$lZL6V/ci$eIMz", "password").is_err());
+		assert!(verify_apr1_hash("$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/", "password").is_err());
+	}
+
+	#[test]
+	fn context_is_wiped() {
+		let mut ctx = MD5Ctx::new();
+		ctx.update_buffer(b"password", 8);
+		ctx.zeroize();
+		assert_eq!(ctx.state, [0; 4]);
+		assert_eq!(ctx.count, [0; 2]);
+		assert_eq!(ctx.buffer, [0; 64]);
+
+		let mut ctx = MD5Ctx::new();
+		ctx.update_buffer(b"password", 8);
+		let mut digest = [0; DIGEST_SIZE];
+		ctx.md5_final(&mut digest);
+		assert_ne!(digest, [0; DIGEST_SIZE]);
+		assert_eq!(ctx.state, [0; 4]);
+		assert_eq!(ctx.buffer, [0; 64]);
+	}
 }
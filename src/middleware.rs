@@ -0,0 +1,395 @@
+use crate::watch::Source;
+#[cfg(feature = "tokio")]
+use crate::{basic::BasicCredentials, AuthenticatedUser, VerifyPool};
+use crate::{basic_auth_challenge, Htpasswd, HtpasswdWatcher, PlaintextPolicy};
+use http::{header, HeaderValue, Request, Response, StatusCode};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Tower layer that requires HTTP Basic credentials listed in an htpasswd file
+///
+/// Requests without a valid `Authorization: Basic` header get an empty `401 Unauthorized`
+/// response with a `WWW-Authenticate` challenge, see
+/// [`basic_auth_challenge`](fn.basic_auth_challenge.html). Authenticated requests are passed
+/// on with an [`AuthenticatedUser`](struct.AuthenticatedUser.html) in their extensions.
+///
+/// The password is verified inside `call`, so slow hashes like bcrypt and Argon2 block the
+/// executor thread for as long as they take. With the `tokio` feature,
+/// [`verify_pool`](#method.verify_pool) moves verification to a
+/// [`VerifyPool`](struct.VerifyPool.html) instead.
+///
+/// Only available with the `tower` feature.
+///
+/// ```
+/// use htpasswd_verify::BasicAuthLayer;
+/// use std::sync::Arc;
+///
+/// let htpasswd = Arc::new(htpasswd_verify::load("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00").into_owned());
+/// let layer = BasicAuthLayer::new(htpasswd).realm("Staff only");
+/// ```
+#[derive(Debug, Clone)]
+pub struct BasicAuthLayer {
+	source: Source,
+	challenge: HeaderValue,
+	plaintext: PlaintextPolicy,
+}
+
+impl BasicAuthLayer {
+	/// Checks requests against a fixed set of users, with the realm `Restricted`
+	pub fn new(htpasswd: Arc<Htpasswd<'static>>) -> Self {
+		BasicAuthLayer::with_source(Source::Fixed(htpasswd))
+	}
+
+	/// Checks requests against the latest version of a watched file
+	pub fn watched(watcher: Arc<HtpasswdWatcher>) -> Self {
+		BasicAuthLayer::with_source(Source::Watched(watcher))
+	}
+
+	fn with_source(source: Source) -> Self {
+		BasicAuthLayer {
+			source,
+			challenge: challenge_header("Restricted"),
+			plaintext: PlaintextPolicy::Reject,
+		}
+	}
+
+	/// Sets the realm sent in the `WWW-Authenticate` challenge. Control characters are left out.
+	pub fn realm(mut self, realm: &str) -> Self {
+		self.challenge = challenge_header(realm);
+		self
+	}
+
+	/// Sets whether plaintext entries are accepted, they are rejected by default
+	pub fn plaintext_policy(mut self, plaintext: PlaintextPolicy) -> Self {
+		self.plaintext = plaintext;
+		self
+	}
+
+	/// Verifies passwords on Tokio's blocking threads through the pool, see
+	/// [`check_async`](struct.Htpasswd.html#method.check_async)
+	///
+	/// Requests that find the pool full get an empty `503 Service Unavailable` response.
+	///
+	/// Only available with the `tokio` feature.
+	#[cfg(feature = "tokio")]
+	pub fn verify_pool(self, pool: VerifyPool) -> PooledBasicAuthLayer {
+		PooledBasicAuthLayer { layer: self, pool }
+	}
+
+	fn unauthorized<B: Default>(&self) -> Response<B> {
+		let mut response = Response::new(B::default());
+		*response.status_mut() = StatusCode::UNAUTHORIZED;
+		response
+			.headers_mut()
+			.insert(header::WWW_AUTHENTICATE, self.challenge.clone());
+		response
+	}
+}
+
+impl<S> Layer<S> for BasicAuthLayer {
+	type Service = BasicAuth<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		BasicAuth {
+			inner,
+			layer: self.clone(),
+		}
+	}
+}
+
+/// Service created by [`BasicAuthLayer`](struct.BasicAuthLayer.html)
+#[derive(Debug, Clone)]
+pub struct BasicAuth<S> {
+	inner: S,
+	layer: BasicAuthLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for BasicAuth<S>
+where
+	S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+	ResBody: Default,
+{
+	type Response = Response<ResBody>;
+	type Error = S::Error;
+	type Future = BasicAuthFuture<S::Future, ResBody>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+		let htpasswd = self.layer.source.load();
+		let user = request
+			.headers()
+			.get(header::AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| {
+				htpasswd
+					.verify_basic_auth_header_with_policy(value, self.layer.plaintext)
+					.ok()
+			});
+		match user {
+			Some(user) => {
+				request.extensions_mut().insert(user);
+				BasicAuthFuture(FutureState::Inner(Box::pin(self.inner.call(request))))
+			}
+			None => BasicAuthFuture(FutureState::Unauthorized(Some(self.layer.unauthorized()))),
+		}
+	}
+}
+
+/// Response future of [`BasicAuth`](struct.BasicAuth.html)
+pub struct BasicAuthFuture<F, B>(FutureState<F, B>);
+
+enum FutureState<F, B> {
+	Inner(Pin<Box<F>>),
+	Unauthorized(Option<Response<B>>),
+}
+
+// The inner future is pinned in its box and the response is never pinned
+impl<F, B> Unpin for BasicAuthFuture<F, B> {}
+
+impl<F, B, E> Future for BasicAuthFuture<F, B>
+where
+	F: Future<Output = Result<Response<B>, E>>,
+{
+	type Output = Result<Response<B>, E>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		match &mut self.get_mut().0 {
+			FutureState::Inner(future) => future.as_mut().poll(cx),
+			FutureState::Unauthorized(response) => Poll::Ready(Ok(response
+				.take()
+				.expect("BasicAuthFuture polled after completion"))),
+		}
+	}
+}
+
+/// Tower layer that verifies HTTP Basic credentials on a
+/// [`VerifyPool`](struct.VerifyPool.html), created by
+/// [`BasicAuthLayer::verify_pool`](struct.BasicAuthLayer.html#method.verify_pool)
+///
+/// Only available with the `tokio` feature.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct PooledBasicAuthLayer {
+	layer: BasicAuthLayer,
+	pool: VerifyPool,
+}
+
+#[cfg(feature = "tokio")]
+impl<S> Layer<S> for PooledBasicAuthLayer {
+	type Service = PooledBasicAuth<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		PooledBasicAuth {
+			inner,
+			layer: self.clone(),
+		}
+	}
+}
+
+/// Service created by [`PooledBasicAuthLayer`](struct.PooledBasicAuthLayer.html)
+///
+/// Only available with the `tokio` feature.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct PooledBasicAuth<S> {
+	inner: S,
+	layer: PooledBasicAuthLayer,
+}
+
+#[cfg(feature = "tokio")]
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for PooledBasicAuth<S>
+where
+	S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+	S::Future: Send,
+	ReqBody: Send + 'static,
+	ResBody: Default + Send + 'static,
+{
+	type Response = Response<ResBody>;
+	type Error = S::Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+		// The service that was polled ready goes into the future, the clone stays for the next
+		// request
+		let clone = self.inner.clone();
+		let mut inner = std::mem::replace(&mut self.inner, clone);
+		let htpasswd = self.layer.layer.source.load();
+		let PooledBasicAuthLayer { layer, pool } = self.layer.clone();
+		let credentials = request
+			.headers()
+			.get(header::AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| BasicCredentials::parse(value).ok());
+		Box::pin(async move {
+			let credentials = match credentials {
+				Some(credentials) => credentials,
+				None => return Ok(layer.unauthorized()),
+			};
+			let username = credentials.username();
+			let checked = htpasswd
+				.check_async_with_policy(&pool, username, credentials.password(), layer.plaintext)
+				.await;
+			match checked {
+				Ok(true) => {
+					request.extensions_mut().insert(AuthenticatedUser {
+						username: username.to_string(),
+					});
+					drop(credentials);
+					inner.call(request).await
+				}
+				Ok(false) => Ok(layer.unauthorized()),
+				Err(_) => {
+					let mut response = Response::new(ResBody::default());
+					*response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+					Ok(response)
+				}
+			}
+		})
+	}
+}
+
+fn challenge_header(realm: &str) -> HeaderValue {
+	let realm = realm
+		.chars()
+		.filter(|c| !c.is_control())
+		.collect::<String>();
+	HeaderValue::from_bytes(basic_auth_challenge(&realm).as_bytes())
+		.expect("challenge without control characters is a valid header value")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{load, AuthenticatedUser};
+	use std::convert::Infallible;
+	use std::future::{ready, Ready};
+
+	/// Echoes the authenticated username
+	#[derive(Clone)]
+	struct Echo;
+
+	impl Service<Request<()>> for Echo {
+		type Response = Response<String>;
+		type Error = Infallible;
+		type Future = Ready<Result<Response<String>, Infallible>>;
+
+		fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn call(&mut self, request: Request<()>) -> Self::Future {
+			let user = request.extensions().get::<AuthenticatedUser>();
+			ready(Ok(Response::new(user.unwrap().username.clone())))
+		}
+	}
+
+	fn send(service: &mut BasicAuth<Echo>, authorization: Option<&str>) -> Response<String> {
+		let mut request = Request::new(());
+		if let Some(authorization) = authorization {
+			request.headers_mut().insert(
+				header::AUTHORIZATION,
+				HeaderValue::from_str(authorization).unwrap(),
+			);
+		}
+		let mut cx = Context::from_waker(std::task::Waker::noop());
+		assert!(service.poll_ready(&mut cx).is_ready());
+		let mut future = service.call(request);
+		match Pin::new(&mut future).poll(&mut cx) {
+			Poll::Ready(Ok(response)) => response,
+			_ => panic!("response isn't ready"),
+		}
+	}
+
+	#[test]
+	fn authenticates_requests() {
+		let htpasswd = Arc::new(load("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00").into_owned());
+		let mut service = BasicAuthLayer::new(htpasswd)
+			.realm("Staff \"only\"\n")
+			.layer(Echo);
+
+		let response = send(&mut service, Some("Basic dXNlcjpwYXNzd29yZA=="));
+		assert_eq!(response.status(), StatusCode::OK);
+		assert_eq!(response.body(), "user");
+
+		for authorization in [None, Some("Basic dXNlcjpwYXNzd29ydA=="), Some("Bearer x")] {
+			let response = send(&mut service, authorization);
+			assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+			assert_eq!(
+				response.headers()[header::WWW_AUTHENTICATE],
+				r#"Basic realm="Staff \"only\"", charset="UTF-8""#
+			);
+			assert_eq!(response.body(), "");
+		}
+	}
+
+	#[cfg(feature = "tokio")]
+	#[tokio::test]
+	async fn pooled_authenticates_requests() {
+		let htpasswd = Arc::new(
+			load("user:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa").into_owned(),
+		);
+		let pool = VerifyPool::new(1, 0);
+		let mut service = BasicAuthLayer::new(htpasswd)
+			.verify_pool(pool.clone())
+			.layer(Echo);
+		let mut send = |authorization: &str| {
+			let mut request = Request::new(());
+			request.headers_mut().insert(
+				header::AUTHORIZATION,
+				HeaderValue::from_str(authorization).unwrap(),
+			);
+			service.call(request)
+		};
+
+		let response = send("Basic dXNlcjpwYXNzd29yZA==").await.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+		assert_eq!(response.body(), "user");
+		let response = send("Basic dXNlcjpwYXNzd29ydA==").await.unwrap();
+		assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+		assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+		let _running = Arc::clone(&pool.queue).try_acquire_owned().unwrap();
+		let response = send("Basic dXNlcjpwYXNzd29yZA==").await.unwrap();
+		assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+	}
+
+	#[cfg(feature = "tokio")]
+	#[test]
+	fn pooled_generates_dummy_off_the_runtime() {
+		use crate::{Hash, Params, Scheme};
+		use std::time::Instant;
+
+		// Rounds no other test uses, so the dummy hash isn't there yet
+		let params = Params {
+			sha_crypt_rounds: Some(20_000),
+			..Params::default()
+		};
+		let started = Instant::now();
+		let hash = Hash::generate("password", Scheme::Sha512Crypt, &params).unwrap();
+		let generating = started.elapsed();
+		let htpasswd = Arc::new(load(&format!("user:{}", hash)).into_owned());
+		let mut service = BasicAuthLayer::new(htpasswd)
+			.verify_pool(VerifyPool::default())
+			.layer(Echo);
+		let mut request = Request::new(());
+		request.headers_mut().insert(
+			header::AUTHORIZATION,
+			HeaderValue::from_static("Basic bm9ib2R5OnBhc3N3b3Jk"),
+		);
+
+		let (response, stall) = crate::tests::longest_stall(service.call(request));
+		assert_eq!(response.unwrap().status(), StatusCode::UNAUTHORIZED);
+		assert!(stall < generating / 2, "{:?} {:?}", stall, generating);
+	}
+}
@@ -0,0 +1,352 @@
+//! Argon2 and scrypt hashes in the PHC string format, `$argon2id$v=19$m=...,t=...,p=...$salt$hash`
+//!
+//! ```
+//! use htpasswd_verify::phc::{hash_argon2, Argon2Params};
+//!
+//! let hash = hash_argon2("password", &Argon2Params::default()).unwrap();
+//! assert!(hash.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+//!
+//! let data = format!("user:{}", hash);
+//! let htpasswd = htpasswd_verify::load(&data);
+//! assert!(htpasswd.check("user", "password"));
+//! ```
+
+use crate::{GenerateError, ParseErrorKind, VerifyError};
+use password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use std::convert::TryFrom;
+
+pub(crate) static ARGON2_IDS: [&str; 3] = ["$argon2id$", "$argon2i$", "$argon2d$"];
+pub(crate) static SCRYPT_ID: &str = "$scrypt$";
+
+/// Most memory an Argon2 hash may use, in KiB. Hashes asking for more are malformed.
+pub const MAX_ARGON2_MEMORY: u32 = 256 * 1024;
+/// Most iterations an Argon2 hash may use. Hashes asking for more are malformed.
+pub const MAX_ARGON2_ITERATIONS: u32 = 32;
+/// Most memory an scrypt hash may use, `128 * r * 2^ln` bytes. Hashes asking for more are
+/// malformed.
+pub const MAX_SCRYPT_MEMORY: u64 = 256 * 1024 * 1024;
+/// Most parallelism an scrypt hash may use. Hashes asking for more are malformed.
+pub const MAX_SCRYPT_PARALLELISM: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argon2Variant {
+	Argon2d,
+	Argon2i,
+	Argon2id,
+}
+
+/// Parameters for [`hash_argon2`](fn.hash_argon2.html), defaults to Argon2id with the
+/// OWASP recommended 19 MiB of memory and 2 iterations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+	pub variant: Argon2Variant,
+	/// Memory size in KiB
+	pub memory: u32,
+	pub iterations: u32,
+	pub parallelism: u32,
+}
+
+impl Default for Argon2Params {
+	fn default() -> Self {
+		Argon2Params {
+			variant: Argon2Variant::Argon2id,
+			memory: argon2::Params::DEFAULT_M_COST,
+			iterations: argon2::Params::DEFAULT_T_COST,
+			parallelism: argon2::Params::DEFAULT_P_COST,
+		}
+	}
+}
+
+/// Parameters for [`hash_scrypt`](fn.hash_scrypt.html), defaults to `ln=17,r=8,p=1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScryptParams {
+	/// Base 2 logarithm of the CPU/memory cost
+	pub log_n: u8,
+	pub block_size: u32,
+	pub parallelism: u32,
+}
+
+impl Default for ScryptParams {
+	fn default() -> Self {
+		ScryptParams {
+			log_n: scrypt::Params::RECOMMENDED_LOG_N,
+			block_size: scrypt::Params::RECOMMENDED_R,
+			parallelism: scrypt::Params::RECOMMENDED_P,
+		}
+	}
+}
+
+/// Hashes the password with Argon2 and a random salt
+pub fn hash_argon2(password: &str, params: &Argon2Params) -> Result<String, GenerateError> {
+	let algorithm = match params.variant {
+		Argon2Variant::Argon2d => argon2::Algorithm::Argon2d,
+		Argon2Variant::Argon2i => argon2::Algorithm::Argon2i,
+		Argon2Variant::Argon2id => argon2::Algorithm::Argon2id,
+	};
+	let argon2_params =
+		argon2::Params::new(params.memory, params.iterations, params.parallelism, None)
+			.ok()
+			.filter(argon2_within_limits)
+			.ok_or(GenerateError::InvalidParams)?;
+	let argon2 = argon2::Argon2::new(algorithm, argon2::Version::V0x13, argon2_params);
+	hash_password(&argon2, password)
+}
+
+/// Hashes the password with scrypt and a random salt
+pub fn hash_scrypt(password: &str, params: &ScryptParams) -> Result<String, GenerateError> {
+	let scrypt_params = scrypt::Params::new(
+		params.log_n,
+		params.block_size,
+		params.parallelism,
+		scrypt::Params::RECOMMENDED_LEN,
+	)
+	.ok()
+	.filter(scrypt_within_limits)
+	.ok_or(GenerateError::InvalidParams)?;
+	let salt = SaltString::generate(&mut OsRng);
+	scrypt::Scrypt
+		.hash_password_customized(password.as_bytes(), None, None, scrypt_params, &salt)
+		.map(|hash| hash.to_string())
+		.map_err(|_| GenerateError::InvalidParams)
+}
+
+fn hash_password(hasher: &impl PasswordHasher, password: &str) -> Result<String, GenerateError> {
+	let salt = SaltString::generate(&mut OsRng);
+	hasher
+		.hash_password(password.as_bytes(), &salt)
+		.map(|hash| hash.to_string())
+		.map_err(|_| GenerateError::InvalidParams)
+}
+
+/// Checks that the hash is a PHC string with a digest and valid Argon2 parameters
+pub(crate) fn parse_argon2(hash: &str) -> Result<(), ParseErrorKind> {
+	let phc = parse_phc(hash)?;
+	argon2_params(&phc).ok_or(ParseErrorKind::MalformedPhc)?;
+	Ok(())
+}
+
+/// Checks that the hash is a PHC string with a digest and valid scrypt parameters
+pub(crate) fn parse_scrypt(hash: &str) -> Result<(), ParseErrorKind> {
+	let phc = parse_phc(hash)?;
+	scrypt_params(&phc).ok_or(ParseErrorKind::MalformedPhc)?;
+	Ok(())
+}
+
+/// Parameters of a valid Argon2 hash, to generate others that take as long to verify
+pub(crate) fn argon2_hash_params(hash: &str) -> Option<Argon2Params> {
+	let phc = parse_phc(hash).ok()?;
+	let params = argon2_params(&phc)?;
+	let variant = match argon2::Algorithm::try_from(phc.algorithm).ok()? {
+		argon2::Algorithm::Argon2d => Argon2Variant::Argon2d,
+		argon2::Algorithm::Argon2i => Argon2Variant::Argon2i,
+		argon2::Algorithm::Argon2id => Argon2Variant::Argon2id,
+	};
+	Some(Argon2Params {
+		variant,
+		memory: params.m_cost(),
+		iterations: params.t_cost(),
+		parallelism: params.p_cost(),
+	})
+}
+
+/// Parameters of a valid scrypt hash, to generate others that take as long to verify
+pub(crate) fn scrypt_hash_params(hash: &str) -> Option<ScryptParams> {
+	let params = scrypt_params(&parse_phc(hash).ok()?)?;
+	Some(ScryptParams {
+		log_n: params.log_n(),
+		block_size: params.r(),
+		parallelism: params.p(),
+	})
+}
+
+fn argon2_params(phc: &PasswordHash<'_>) -> Option<argon2::Params> {
+	argon2::Params::try_from(phc)
+		.ok()
+		.filter(argon2_within_limits)
+}
+
+fn scrypt_params(phc: &PasswordHash<'_>) -> Option<scrypt::Params> {
+	scrypt::Params::try_from(phc)
+		.ok()
+		.filter(scrypt_within_limits)
+}
+
+fn argon2_within_limits(params: &argon2::Params) -> bool {
+	params.m_cost() <= MAX_ARGON2_MEMORY && params.t_cost() <= MAX_ARGON2_ITERATIONS
+}
+
+fn scrypt_within_limits(params: &scrypt::Params) -> bool {
+	// scrypt rejects log_n of 64 and more, which would overflow the shift
+	let memory = (128 * u128::from(params.r())) << params.log_n();
+	memory <= u128::from(MAX_SCRYPT_MEMORY) && params.p() <= MAX_SCRYPT_PARALLELISM
+}
+
+fn parse_phc(hash: &str) -> Result<PasswordHash<'_>, ParseErrorKind> {
+	PasswordHash::new(hash)
+		.ok()
+		.filter(|phc| phc.hash.is_some())
+		.ok_or(ParseErrorKind::MalformedPhc)
+}
+
+pub(crate) fn verify_argon2(hash: &str, password: &str) -> Result<bool, VerifyError> {
+	let phc = PasswordHash::new(hash).map_err(|_| VerifyError::MalformedHash)?;
+	let params = argon2_params(&phc).ok_or(VerifyError::MalformedHash)?;
+	verify(&argon2::Argon2::default(), &phc, params, password)
+}
+
+pub(crate) fn verify_scrypt(hash: &str, password: &str) -> Result<bool, VerifyError> {
+	let phc = PasswordHash::new(hash).map_err(|_| VerifyError::MalformedHash)?;
+	let params = scrypt_params(&phc).ok_or(VerifyError::MalformedHash)?;
+	verify(&scrypt::Scrypt, &phc, params, password)
+}
+
+/// Hashes the password with the parameters and salt of the stored hash and compares the digests
+fn verify<H: PasswordHasher>(
+	hasher: &H,
+	phc: &PasswordHash<'_>,
+	params: H::Params,
+	password: &str,
+) -> Result<bool, VerifyError> {
+	let (expected, salt) = phc.hash.zip(phc.salt).ok_or(VerifyError::MalformedHash)?;
+	let computed = hasher
+		.hash_password_customized(
+			password.as_bytes(),
+			Some(phc.algorithm),
+			phc.version,
+			params,
+			salt,
+		)
+		.map_err(|_| VerifyError::MalformedHash)?
+		.hash
+		.ok_or(VerifyError::MalformedHash)?;
+	Ok(crate::constant_time_eq(
+		computed.as_bytes(),
+		expected.as_bytes(),
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Hash;
+
+	static DATA: &str = "argon2id:$argon2id$v=19$m=256,t=2,p=1$c29tZXNhbHQ$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4
+argon2i:$argon2i$v=16$m=256,t=2,p=1$c29tZXNhbHQ$/U3YPXYsSb3q9XxHvc0MLxur+GP960kN9j7emXX8zwY
+scrypt:$scrypt$ln=4,r=8,p=1$c2FsdHNhbHRzYWx0c2FsdA$5f/Vi+XRWGUNGScbsma6KJ4zLFIke/NJsrvr7lQLAyA";
+
+	#[test]
+	fn phc_verify() {
+		let htpasswd = crate::try_load(DATA).unwrap();
+		for &username in ["argon2id", "argon2i", "scrypt"].iter() {
+			assert!(matches!(
+				htpasswd.0[username],
+				Hash::Argon2(_) | Hash::Scrypt(_)
+			));
+			assert!(htpasswd.check(username, "password"), "{}", username);
+			assert!(!htpasswd.check(username, "passwort"), "{}", username);
+		}
+	}
+
+	#[test]
+	fn phc_generate() {
+		let argon2 = hash_argon2(
+			"password",
+			&Argon2Params {
+				variant: Argon2Variant::Argon2i,
+				memory: 256,
+				iterations: 1,
+				parallelism: 1,
+			},
+		)
+		.unwrap();
+		assert!(argon2.starts_with("$argon2i$v=19$m=256,t=1,p=1$"));
+		assert_eq!(Hash::parse(&argon2).verify("password"), Ok(true));
+
+		let scrypt = hash_scrypt(
+			"password",
+			&ScryptParams {
+				log_n: 4,
+				block_size: 8,
+				parallelism: 1,
+			},
+		)
+		.unwrap();
+		assert!(scrypt.starts_with("$scrypt$ln=4,r=8,p=1$"));
+		assert_eq!(Hash::parse(&scrypt).verify("password"), Ok(true));
+		assert_ne!(
+			hash_scrypt(
+				"password",
+				&ScryptParams {
+					log_n: 4,
+					block_size: 8,
+					parallelism: 1,
+				}
+			),
+			Ok(scrypt)
+		);
+	}
+
+	#[test]
+	fn phc_invalid_params() {
+		let params = Argon2Params {
+			memory: 1,
+			..Argon2Params::default()
+		};
+		assert_eq!(
+			hash_argon2("password", &params),
+			Err(GenerateError::InvalidParams)
+		);
+		assert_eq!(
+			Hash::try_parse("$argon2id$v=19$m=256,t=2,p=1$c29tZXNhbHQ").unwrap_err(),
+			ParseErrorKind::MalformedPhc
+		);
+		assert_eq!(
+			Hash::try_parse("$scrypt$ln=99,r=8,p=1$aM15713r3Xsvxbi31lqr1Q$nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E").unwrap_err(),
+			ParseErrorKind::MalformedPhc
+		);
+	}
+
+	#[test]
+	fn phc_params_over_limits() {
+		let too_costly = [
+			"$argon2id$v=19$m=4294967295,t=2,p=1$c29tZXNhbHQ$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4",
+			"$argon2id$v=19$m=256,t=33,p=1$c29tZXNhbHQ$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4",
+			"$scrypt$ln=40,r=8,p=1$c2FsdHNhbHRzYWx0c2FsdA$5f/Vi+XRWGUNGScbsma6KJ4zLFIke/NJsrvr7lQLAyA",
+			"$scrypt$ln=19,r=8,p=1$c2FsdHNhbHRzYWx0c2FsdA$5f/Vi+XRWGUNGScbsma6KJ4zLFIke/NJsrvr7lQLAyA",
+			"$scrypt$ln=4,r=8,p=17$c2FsdHNhbHRzYWx0c2FsdA$5f/Vi+XRWGUNGScbsma6KJ4zLFIke/NJsrvr7lQLAyA",
+		];
+		for &hash in too_costly.iter() {
+			assert_eq!(
+				Hash::try_parse(hash).unwrap_err(),
+				ParseErrorKind::MalformedPhc,
+				"{}",
+				hash
+			);
+			assert_eq!(
+				Hash::parse(hash).verify("password"),
+				Err(VerifyError::MalformedHash)
+			);
+		}
+		assert_eq!(
+			verify_argon2(too_costly[0], "password"),
+			Err(VerifyError::MalformedHash)
+		);
+		assert_eq!(
+			verify_scrypt(too_costly[2], "password"),
+			Err(VerifyError::MalformedHash)
+		);
+
+		let params = Argon2Params {
+			memory: MAX_ARGON2_MEMORY + 1,
+			..Argon2Params::default()
+		};
+		assert_eq!(
+			hash_argon2("password", &params),
+			Err(GenerateError::InvalidParams)
+		);
+		// 128 * 8 * 2^18 bytes is exactly the limit
+		assert!(scrypt_within_limits(
+			&scrypt::Params::new(18, 8, 1, 32).unwrap()
+		));
+	}
+}
@@ -0,0 +1,213 @@
+use crate::{Htpasswd, PlaintextPolicy, PoolError, SecretPassword};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Limits how many passwords are verified at once on Tokio's blocking threads
+///
+/// Up to `max_concurrent` verifications run at a time and up to `max_queued` more wait for a
+/// slot. Further calls fail right away with
+/// [`PoolError::Overloaded`](enum.PoolError.html#variant.Overloaded) instead of piling up, so
+/// a burst of login attempts can't tie up the runtime. Clones share the same limits.
+///
+/// Only available with the `tokio` feature.
+#[derive(Debug, Clone)]
+pub struct VerifyPool {
+	/// Slots for running and waiting verifications
+	pub(crate) queue: Arc<Semaphore>,
+	/// Slots for running verifications
+	pub(crate) running: Arc<Semaphore>,
+}
+
+impl VerifyPool {
+	pub fn new(max_concurrent: usize, max_queued: usize) -> Self {
+		assert!(max_concurrent > 0, "max_concurrent must be at least 1");
+		VerifyPool {
+			queue: Arc::new(Semaphore::new(max_concurrent + max_queued)),
+			running: Arc::new(Semaphore::new(max_concurrent)),
+		}
+	}
+}
+
+/// One verification per CPU, with 16 more waiting per CPU
+impl Default for VerifyPool {
+	fn default() -> Self {
+		let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+		VerifyPool::new(cpus, cpus * 16)
+	}
+}
+
+impl Htpasswd<'_> {
+	/// Verifies the user's password on Tokio's blocking threads, rejecting plaintext entries
+	///
+	/// Returns `Ok(false)` for unknown users and wrong passwords like
+	/// [`check`](#method.check), and an error if the pool is full. Unknown users are verified
+	/// against a dummy entry in the pool like
+	/// [`check_without_enumeration`](#method.check_without_enumeration), so they take as long
+	/// and can be rejected as overloaded the same way. Must be called from within a Tokio runtime.
+	///
+	/// Only available with the `tokio` feature.
+	///
+	/// ```
+	/// use htpasswd_verify::VerifyPool;
+	///
+	/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+	/// let htpasswd = htpasswd_verify::load("user:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa");
+	/// let pool = VerifyPool::new(4, 64);
+	/// assert_eq!(htpasswd.check_async(&pool, "user", "password").await, Ok(true));
+	/// # });
+	/// ```
+	pub async fn check_async(
+		&self,
+		pool: &VerifyPool,
+		username: &str,
+		password: impl Into<SecretPassword>,
+	) -> Result<bool, PoolError> {
+		self.check_async_with_policy(pool, username, password, PlaintextPolicy::Reject)
+			.await
+	}
+
+	/// Verifies the user's password on Tokio's blocking threads
+	///
+	/// See [`check_async`](#method.check_async).
+	pub async fn check_async_with_policy(
+		&self,
+		pool: &VerifyPool,
+		username: &str,
+		password: impl Into<SecretPassword>,
+		plaintext: PlaintextPolicy,
+	) -> Result<bool, PoolError> {
+		// Picked for known users too, so both take the same path
+		let dummy = std::hint::black_box(self.dummy_template());
+		let hash = self.0.get(username).map(|hash| hash.clone().into_owned());
+		let password = password.into();
+
+		let queued = Arc::clone(&pool.queue)
+			.try_acquire_owned()
+			.map_err(|_| PoolError::Overloaded)?;
+		let running = Arc::clone(&pool.running)
+			.acquire_owned()
+			.await
+			.map_err(|_| PoolError::ShutDown)?;
+		// The permits move into the task, so a caller that stops waiting doesn't free the slot
+		// before the verification is done
+		let task = tokio::task::spawn_blocking(move || {
+			let _permits = (queued, running);
+			// The first dummy of a scheme and cost takes as long to generate as a verification,
+			// so it's looked up here rather than on the runtime
+			let dummy = std::hint::black_box(dummy.and_then(|dummy| dummy.hash()));
+			match hash {
+				Some(hash) => hash.check_with_policy(&password, plaintext),
+				None => {
+					std::hint::black_box(
+						dummy.map(|dummy| dummy.check_with_policy(&password, plaintext)),
+					);
+					false
+				}
+			}
+		});
+		match task.await {
+			Ok(valid) => Ok(valid),
+			Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+			Err(_) => Err(PoolError::ShutDown),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::longest_stall;
+	use crate::{load, Hash, Params, Scheme};
+	use std::time::Instant;
+
+	static DATA: &str = "user:$2y$05$nC6nErr9XZJuMJ57WyCob.EuZEjylDt2KaHfbfOtyb.EgL1I2jCVa";
+
+	#[tokio::test]
+	async fn check_async_verifies() {
+		let htpasswd = load(DATA);
+		let pool = VerifyPool::default();
+		assert_eq!(
+			htpasswd.check_async(&pool, "user", "password").await,
+			Ok(true)
+		);
+		assert_eq!(
+			htpasswd.check_async(&pool, "user", "passwort").await,
+			Ok(false)
+		);
+		assert_eq!(
+			htpasswd
+				.check_async(&pool, "nobody", String::from("password"))
+				.await,
+			Ok(false)
+		);
+		let plain = load("plain:password");
+		assert_eq!(
+			plain.check_async(&pool, "plain", "password").await,
+			Ok(false)
+		);
+		assert_eq!(
+			plain
+				.check_async_with_policy(
+					&pool,
+					"plain",
+					"password",
+					PlaintextPolicy::AllowPlaintext
+				)
+				.await,
+			Ok(true)
+		);
+	}
+
+	#[tokio::test]
+	async fn check_async_reports_overload() {
+		let htpasswd = Arc::new(load(DATA).into_owned());
+		let pool = VerifyPool::new(1, 1);
+		// Pretend a verification is running, so the next call has to wait in the queue
+		let running = (
+			Arc::clone(&pool.queue).try_acquire_owned().unwrap(),
+			Arc::clone(&pool.running).try_acquire_owned().unwrap(),
+		);
+		let waiting = tokio::spawn({
+			let htpasswd = Arc::clone(&htpasswd);
+			let pool = pool.clone();
+			async move { htpasswd.check_async(&pool, "user", "password").await }
+		});
+		while pool.queue.available_permits() > 0 {
+			tokio::task::yield_now().await;
+		}
+		assert_eq!(
+			htpasswd.check_async(&pool, "user", "password").await,
+			Err(PoolError::Overloaded)
+		);
+		// Unknown users wait for a slot like everyone else
+		assert_eq!(
+			htpasswd.check_async(&pool, "nobody", "password").await,
+			Err(PoolError::Overloaded)
+		);
+
+		drop(running);
+		assert_eq!(waiting.await.unwrap(), Ok(true));
+		assert_eq!(
+			htpasswd.check_async(&pool, "user", "password").await,
+			Ok(true)
+		);
+	}
+
+	#[test]
+	fn check_async_generates_dummy_off_the_runtime() {
+		// A cost no other test uses, so the dummy hash isn't there yet
+		let params = Params {
+			bcrypt_cost: 10,
+			..Params::default()
+		};
+		let started = Instant::now();
+		let hash = Hash::generate("password", Scheme::BCrypt, &params).unwrap();
+		let generating = started.elapsed();
+		let htpasswd = load(&format!("user:{}", hash)).into_owned();
+		let pool = VerifyPool::default();
+
+		let (valid, stall) = longest_stall(htpasswd.check_async(&pool, "nobody", "password"));
+		assert_eq!(valid, Ok(false));
+		assert!(stall < generating / 2, "{:?} {:?}", stall, generating);
+	}
+}
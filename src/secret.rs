@@ -0,0 +1,82 @@
+use std::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Password that is wiped from memory when dropped and never printed
+///
+/// Accepted by every `check` and `verify` method in place of a `&str`.
+///
+/// ```
+/// use htpasswd_verify::SecretPassword;
+///
+/// let password = SecretPassword::from(String::from("password"));
+/// assert_eq!(format!("{:?}", password), "SecretPassword(..)");
+///
+/// let htpasswd = htpasswd_verify::load("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00");
+/// assert!(htpasswd.check("user", &password));
+/// ```
+#[derive(Clone, Default)]
+pub struct SecretPassword(Zeroizing<String>);
+
+impl SecretPassword {
+	pub fn new(password: String) -> Self {
+		SecretPassword(Zeroizing::new(password))
+	}
+
+	pub fn expose(&self) -> &str {
+		&self.0
+	}
+}
+
+impl From<String> for SecretPassword {
+	fn from(password: String) -> Self {
+		SecretPassword::new(password)
+	}
+}
+
+impl From<&str> for SecretPassword {
+	fn from(password: &str) -> Self {
+		SecretPassword::new(password.to_string())
+	}
+}
+
+impl AsRef<str> for SecretPassword {
+	fn as_ref(&self) -> &str {
+		self.expose()
+	}
+}
+
+impl Zeroize for SecretPassword {
+	fn zeroize(&mut self) {
+		self.0.zeroize();
+	}
+}
+
+impl ZeroizeOnDrop for SecretPassword {}
+
+impl fmt::Debug for SecretPassword {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("SecretPassword(..)")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{load, Hash, PlaintextPolicy, VerifyError};
+
+	#[test]
+	fn accepted_by_verifiers() {
+		let password = SecretPassword::from("password");
+		let wrong = SecretPassword::new(String::from("passwort"));
+		let htpasswd = load("user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00\nplain:password");
+		assert!(htpasswd.check("user", &password));
+		assert!(!htpasswd.check("user", &wrong));
+		assert_eq!(
+			htpasswd.verify("nobody", &password),
+			Err(VerifyError::UnknownUser)
+		);
+		assert!(htpasswd.check_with_policy("plain", &password, PlaintextPolicy::AllowPlaintext));
+		assert!(Hash::parse("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=").check(&password));
+		assert_eq!(format!("{:?}", wrong), "SecretPassword(..)");
+	}
+}
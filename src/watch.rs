@@ -0,0 +1,422 @@
+use crate::{Htpasswd, ReloadError};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// Outcome of a reload, passed to the callback of
+/// [`HtpasswdWatcher::watch_with`](struct.HtpasswdWatcher.html#method.watch_with)
+#[derive(Debug)]
+pub enum ReloadEvent {
+	/// File changed and the new version is in use
+	Reloaded(Arc<Htpasswd<'static>>),
+	/// File changed but couldn't be loaded, the last good version stays in use
+	Failed(ReloadError),
+}
+
+/// Options for [`HtpasswdWatcher::watch_with`](struct.HtpasswdWatcher.html#method.watch_with)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchOptions {
+	/// How often the file is checked when it's polled, 2 seconds by default
+	pub poll_interval: Duration,
+	/// Poll even where inotify is available
+	pub force_polling: bool,
+}
+
+impl Default for WatchOptions {
+	fn default() -> Self {
+		WatchOptions {
+			poll_interval: Duration::from_secs(2),
+			force_polling: false,
+		}
+	}
+}
+
+/// htpasswd file that is reloaded in the background when it changes
+///
+/// The file counts as changed when its modification time, inode or size changes. Changes are
+/// picked up through inotify on Linux and by polling elsewhere. A file that fails to load
+/// keeps the last good version in use.
+///
+/// A file that is rewritten in place can be read halfway through the write. If the file
+/// changed while it was read, it's read again on the next check, but a file that was just
+/// truncated and not written yet loads as empty. Writers should replace the file by renaming a
+/// new one over it, like [`HtpasswdFile::save`](struct.HtpasswdFile.html#method.save) does.
+///
+/// ```no_run
+/// use htpasswd_verify::HtpasswdWatcher;
+///
+/// let watcher = HtpasswdWatcher::watch("/etc/apache2/.htpasswd").unwrap();
+/// assert!(watcher.load().check("user", "password"));
+/// ```
+pub struct HtpasswdWatcher {
+	shared: Arc<Shared>,
+	stop: Option<mpsc::Sender<()>>,
+	thread: Option<JoinHandle<()>>,
+	#[cfg(target_os = "linux")]
+	inotify_watch: Option<(inotify::Watches, inotify::WatchDescriptor)>,
+}
+
+type Callback = Box<dyn Fn(ReloadEvent) + Send + Sync>;
+
+struct Shared {
+	path: PathBuf,
+	current: RwLock<Arc<Htpasswd<'static>>>,
+	fingerprint: Mutex<Option<Fingerprint>>,
+	on_reload: Callback,
+}
+
+/// What is compared to tell whether the file changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fingerprint {
+	modified: Option<SystemTime>,
+	inode: u64,
+	size: u64,
+}
+
+impl HtpasswdWatcher {
+	/// Loads the file and starts watching it with the default options
+	pub fn watch(path: impl AsRef<Path>) -> Result<Self, ReloadError> {
+		Self::watch_with(path, WatchOptions::default(), |_| {})
+	}
+
+	/// Loads the file and starts watching it, calling `on_reload` from the watcher thread after
+	/// every reload attempt.
+	///
+	/// Fails if the file can't be loaded initially. To receive events on a channel, send them
+	/// from the callback:
+	///
+	/// ```no_run
+	/// use htpasswd_verify::{HtpasswdWatcher, WatchOptions};
+	///
+	/// let (sender, events) = std::sync::mpsc::channel();
+	/// let on_reload = move |event| sender.send(event).unwrap_or_default();
+	/// let watcher = HtpasswdWatcher::watch_with(".htpasswd", WatchOptions::default(), on_reload);
+	/// ```
+	pub fn watch_with(
+		path: impl AsRef<Path>,
+		options: WatchOptions,
+		on_reload: impl Fn(ReloadEvent) + Send + Sync + 'static,
+	) -> Result<Self, ReloadError> {
+		let path = path.as_ref().to_path_buf();
+		let fingerprint = fingerprint(&path).map_err(ReloadError::Io)?;
+		let htpasswd = load_file(&path)?;
+		let shared = Arc::new(Shared {
+			path,
+			current: RwLock::new(Arc::new(htpasswd)),
+			fingerprint: Mutex::new(Some(fingerprint)),
+			on_reload: Box::new(on_reload),
+		});
+		let (stop, stopped) = mpsc::channel();
+		let mut watcher = HtpasswdWatcher {
+			shared: Arc::clone(&shared),
+			stop: Some(stop),
+			thread: None,
+			#[cfg(target_os = "linux")]
+			inotify_watch: None,
+		};
+
+		#[cfg(target_os = "linux")]
+		if !options.force_polling {
+			if let Ok((inotify, watch)) = inotify_watch(&shared.path) {
+				watcher.inotify_watch = Some((inotify.watches(), watch));
+				watcher.thread = Some(thread::spawn(move || {
+					watch_inotify(&shared, inotify, &stopped, options.poll_interval)
+				}));
+				// Changes made before the watch was added
+				watcher.reload();
+				return Ok(watcher);
+			}
+		}
+
+		watcher.thread = Some(thread::spawn(move || {
+			poll(&shared, &stopped, options.poll_interval)
+		}));
+		Ok(watcher)
+	}
+
+	/// Current version of the file
+	pub fn load(&self) -> Arc<Htpasswd<'static>> {
+		Arc::clone(
+			&self
+				.shared
+				.current
+				.read()
+				.unwrap_or_else(PoisonError::into_inner),
+		)
+	}
+
+	/// Checks the file right away, reloading it if it changed
+	pub fn reload(&self) {
+		self.shared.refresh();
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.shared.path
+	}
+}
+
+impl Drop for HtpasswdWatcher {
+	fn drop(&mut self) {
+		self.stop.take();
+		// Removing the watch wakes up the thread blocked on reading inotify events
+		#[cfg(target_os = "linux")]
+		if let Some((mut watches, watch)) = self.inotify_watch.take() {
+			let _ = watches.remove(watch);
+		}
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+impl std::fmt::Debug for HtpasswdWatcher {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("HtpasswdWatcher")
+			.field("path", &self.shared.path)
+			.finish()
+	}
+}
+
+/// Fixed or watched htpasswd file, for wrappers that take either
+#[derive(Debug, Clone)]
+pub(crate) enum Source {
+	Fixed(Arc<Htpasswd<'static>>),
+	Watched(Arc<HtpasswdWatcher>),
+}
+
+impl Source {
+	pub(crate) fn load(&self) -> Arc<Htpasswd<'static>> {
+		match self {
+			Source::Fixed(htpasswd) => Arc::clone(htpasswd),
+			Source::Watched(watcher) => watcher.load(),
+		}
+	}
+}
+
+impl Shared {
+	fn refresh(&self) {
+		let mut last = self
+			.fingerprint
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+		let event = match fingerprint(&self.path) {
+			Ok(current) if Some(current) == *last => return,
+			Ok(current) => {
+				let loaded = load_file(&self.path);
+				// Caught in the middle of a write, the next check reads it again
+				if fingerprint(&self.path).ok() != Some(current) {
+					return;
+				}
+				*last = Some(current);
+				match loaded {
+					Ok(htpasswd) => {
+						let htpasswd = Arc::new(htpasswd);
+						*self.current.write().unwrap_or_else(PoisonError::into_inner) =
+							Arc::clone(&htpasswd);
+						ReloadEvent::Reloaded(htpasswd)
+					}
+					Err(err) => ReloadEvent::Failed(err),
+				}
+			}
+			// Only reported once, until the file is back
+			Err(_) if last.is_none() => return,
+			Err(err) => {
+				*last = None;
+				ReloadEvent::Failed(ReloadError::Io(err))
+			}
+		};
+		drop(last);
+		(self.on_reload)(event);
+	}
+}
+
+fn load_file(path: &Path) -> Result<Htpasswd<'static>, ReloadError> {
+	let data = fs::read_to_string(path).map_err(ReloadError::Io)?;
+	Htpasswd::parse(&data)
+		.map(Htpasswd::into_owned)
+		.map_err(ReloadError::Parse)
+}
+
+fn fingerprint(path: &Path) -> io::Result<Fingerprint> {
+	let metadata = fs::metadata(path)?;
+	#[cfg(unix)]
+	let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+	#[cfg(not(unix))]
+	let inode = 0;
+	Ok(Fingerprint {
+		modified: metadata.modified().ok(),
+		inode,
+		size: metadata.len(),
+	})
+}
+
+fn poll(shared: &Shared, stopped: &mpsc::Receiver<()>, interval: Duration) {
+	while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+		shared.refresh();
+	}
+}
+
+/// Watches the directory, since saving a file usually renames a new one over it
+#[cfg(target_os = "linux")]
+fn inotify_watch(path: &Path) -> io::Result<(inotify::Inotify, inotify::WatchDescriptor)> {
+	use inotify::{Inotify, WatchMask};
+
+	let dir = match path.parent() {
+		Some(parent) if !parent.as_os_str().is_empty() => parent,
+		_ => Path::new("."),
+	};
+	let inotify = Inotify::init()?;
+	let watch = inotify.watches().add(
+		dir,
+		WatchMask::CLOSE_WRITE
+			| WatchMask::MOVED_TO
+			| WatchMask::MOVED_FROM
+			| WatchMask::CREATE
+			| WatchMask::DELETE
+			| WatchMask::ATTRIB,
+	)?;
+	Ok((inotify, watch))
+}
+
+#[cfg(target_os = "linux")]
+fn watch_inotify(
+	shared: &Shared,
+	mut inotify: inotify::Inotify,
+	stopped: &mpsc::Receiver<()>,
+	poll_interval: Duration,
+) {
+	let file_name = shared.path.file_name();
+	let mut buffer = [0u8; 4096];
+	loop {
+		let mut changed = false;
+		let mut watch_removed = false;
+		match inotify.read_events_blocking(&mut buffer) {
+			Ok(events) => {
+				for event in events {
+					if event.mask.contains(inotify::EventMask::IGNORED) {
+						watch_removed = true;
+					} else if event.name == file_name {
+						changed = true;
+					}
+				}
+			}
+			Err(_) => watch_removed = true,
+		}
+		if let Err(TryRecvError::Disconnected) = stopped.try_recv() {
+			return;
+		}
+		if watch_removed {
+			// The directory is gone, keep going by polling until it's back
+			return poll(shared, stopped, poll_interval);
+		}
+		if changed {
+			shared.refresh();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::test_dir;
+
+	static DATA: &str = "user:$apr1$lZL6V/ci$eIMz/iKDkbtys/uU7LEK00\n";
+
+	fn test_file(name: &str) -> PathBuf {
+		let path = test_dir(&format!("watch-{}", name)).join("htpasswd");
+		fs::write(&path, DATA).unwrap();
+		path
+	}
+
+	fn watch(path: &Path, options: WatchOptions) -> (HtpasswdWatcher, mpsc::Receiver<ReloadEvent>) {
+		let (sender, events) = mpsc::channel();
+		let watcher = HtpasswdWatcher::watch_with(path, options, move |event| {
+			let _ = sender.send(event);
+		})
+		.unwrap();
+		(watcher, events)
+	}
+
+	fn next_event(events: &mpsc::Receiver<ReloadEvent>) -> ReloadEvent {
+		events.recv_timeout(Duration::from_secs(10)).unwrap()
+	}
+
+	fn reloads_and_keeps_last_good(options: WatchOptions, name: &str) {
+		let path = test_file(name);
+		let (watcher, events) = watch(&path, options);
+		assert!(watcher.load().check("user", "password"));
+
+		// Replaced by renaming a new file over it, like HtpasswdFile::save does
+		let temp = path.with_file_name("htpasswd.tmp");
+		fs::write(
+			&temp,
+			format!("{}new:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n", DATA),
+		)
+		.unwrap();
+		fs::rename(&temp, &path).unwrap();
+		match next_event(&events) {
+			ReloadEvent::Reloaded(htpasswd) => assert!(htpasswd.check("new", "password")),
+			event => panic!("unexpected event {:?}", event),
+		}
+		assert!(watcher.load().check("new", "password"));
+
+		// Renamed over the file, so polling can't see it truncated but not written yet
+		let temp_path = path.with_file_name("htpasswd.tmp");
+		fs::write(&temp_path, "broken:$apr1$lZL6\n").unwrap();
+		fs::rename(&temp_path, &path).unwrap();
+		match next_event(&events) {
+			ReloadEvent::Failed(ReloadError::Parse(err)) => {
+				assert_eq!(err.username.as_deref(), Some("broken"))
+			}
+			event => panic!("unexpected event {:?}", event),
+		}
+		assert!(watcher.load().check("new", "password"));
+
+		drop(watcher);
+		fs::remove_dir_all(path.parent().unwrap()).unwrap();
+	}
+
+	#[test]
+	fn watcher_polls() {
+		let options = WatchOptions {
+			poll_interval: Duration::from_millis(10),
+			force_polling: true,
+		};
+		reloads_and_keeps_last_good(options, "poll");
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn watcher_uses_inotify() {
+		let options = WatchOptions {
+			poll_interval: Duration::from_secs(3600),
+			force_polling: false,
+		};
+		reloads_and_keeps_last_good(options, "inotify");
+	}
+
+	#[test]
+	fn watcher_initial_load_and_missing_file() {
+		let path = test_file("missing");
+		assert!(matches!(
+			HtpasswdWatcher::watch(path.with_file_name("nothing")),
+			Err(ReloadError::Io(_))
+		));
+
+		let (watcher, events) = watch(&path, WatchOptions::default());
+		fs::remove_file(&path).unwrap();
+		watcher.reload();
+		assert!(matches!(
+			next_event(&events),
+			ReloadEvent::Failed(ReloadError::Io(_))
+		));
+		watcher.reload();
+		assert!(watcher.load().check("user", "password"));
+		drop(watcher);
+		fs::remove_dir_all(path.parent().unwrap()).unwrap();
+	}
+}